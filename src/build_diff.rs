@@ -0,0 +1,72 @@
+use std::{
+    collections::{BTreeMap},
+};
+
+use crate::{
+    NovelInfo, NovelResult, RunInfo,
+    novel::{
+        self,
+        manifest::{self, ChapterManifest},
+    },
+};
+
+// Compares the manifests from the last build with the table of contents right now
+//  Only the main pages get fetched, so it's quick enough to run before deciding to rebuild
+pub fn diff_novels(run_info: &RunInfo) {
+    for novel_info in run_info.novel_batch() {
+        if novel_info.local_dir.is_some() {
+            println!("{}: Local novels can't be compared", &novel_info.short_name);
+            continue;
+        }
+        if let Err(e) = diff_novel(novel_info, run_info) {
+            println!("Failed to compare {}: {:?}", &novel_info.short_name, e);
+        }
+    }
+}
+
+fn diff_novel(novel_info: &NovelInfo, run_info: &RunInfo) -> NovelResult<()> {
+    let (uri, novel_site) = crate::find_novel_site(&novel_info.url)?;
+    let stored_chapters = manifest::load_chapter_manifests(&novel::book_dirs(run_info),
+        &uri.to_string())?;
+    if stored_chapters.is_empty() {
+        println!("{}: There aren't any manifests to compare with. Build it with write_manifest",
+            &novel_info.short_name);
+        return Ok(());
+    }
+    let fresh_chapters = novel_site.fetch_chapter_list(uri, novel_info)?;
+
+    let stored_chapters: BTreeMap<u32, &ChapterManifest> = stored_chapters.iter()
+        .map(|chapter| (chapter.order_num, chapter))
+        .collect();
+    let mut changes = Vec::new();
+    for fresh in fresh_chapters.iter() {
+        match stored_chapters.get(&fresh.order_num) {
+            None => changes.push(format!("+ {} {}", fresh.order_num, &fresh.name)),
+            Some(stored) => {
+                if stored.name != fresh.name {
+                    changes.push(format!("~ {} {} -> {}", fresh.order_num, &stored.name,
+                        &fresh.name));
+                }
+                if stored.date != fresh.date {
+                    changes.push(format!("~ {} {} was edited ({} -> {})", fresh.order_num,
+                        &fresh.name, &stored.date, &fresh.date));
+                }
+            },
+        }
+    }
+    for (order_num, stored) in stored_chapters.iter() {
+        if !fresh_chapters.iter().any(|fresh| fresh.order_num == *order_num) {
+            changes.push(format!("- {} {}", order_num, &stored.name));
+        }
+    }
+
+    if changes.is_empty() {
+        println!("{}: Nothing has changed", &novel_info.short_name);
+    } else {
+        println!("{}: {} changes", &novel_info.short_name, changes.len());
+        for change in changes {
+            println!("    {}", change);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use std::{
+    fs,
+    path::{Path},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use isahc::http::{Uri};
+
+use crate::{NovelResult};
+
+// Browsers mark HttpOnly cookies like a comment, but they still need to be sent
+const HTTP_ONLY_PREFIX: &'static str = "#HttpOnly_";
+
+// Cookies from a cookies.txt file (the Netscape format), like the ones browser extensions export
+//  Nothing ever gets added, so a logged in browser has to export it again once they expire
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+impl CookieJar {
+    pub fn load(cookies_path: &Path) -> NovelResult<CookieJar> {
+        let cookies_text = fs::read_to_string(cookies_path)?;
+        let mut cookies = Vec::new();
+        for (i, line) in cookies_text.lines().enumerate() {
+            let line = line.trim();
+            let line = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+                Some(line) => line,
+                None if line.is_empty() || line.starts_with('#') => continue,
+                None => line,
+            };
+            match Cookie::parse(line) {
+                Some(cookie) => cookies.push(cookie),
+                None => println!("Skipping line {} of {:?} since it isn't a cookie",
+                    i + 1, cookies_path),
+            }
+        }
+        println!("Loaded {} cookies from {:?}", cookies.len(), cookies_path);
+        Ok(CookieJar { cookies })
+    }
+
+    // Gives back the whole Cookie header, if there's anything to send to this page
+    pub fn cookie_header(&self, uri: &Uri) -> Option<String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs())
+            .unwrap_or(0);
+        let cookie_pairs: Vec<String> = self.cookies.iter()
+            .filter(|cookie| cookie.matches(uri, now))
+            .map(|cookie| format!("{}={}", &cookie.name, &cookie.value))
+            .collect();
+        if cookie_pairs.is_empty() {
+            None
+        } else {
+            Some(cookie_pairs.join("; "))
+        }
+    }
+}
+
+struct Cookie {
+    // Without the leading dot
+    domain: String,
+    include_subdomains: bool,
+    path: String,
+    secure_only: bool,
+    // Seconds since the epoch. 0 is for cookies that only last for the session
+    expires: u64,
+    name: String,
+    value: String,
+}
+impl Cookie {
+    // domain, include subdomains, path, secure, expires, name, value (all split by tabs)
+    fn parse(line: &str) -> Option<Cookie> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 7 {
+            return None;
+        }
+        Some(Cookie {
+            domain: fields[0].trim_start_matches('.').to_lowercase(),
+            include_subdomains: fields[1].eq_ignore_ascii_case("TRUE"),
+            path: fields[2].to_string(),
+            secure_only: fields[3].eq_ignore_ascii_case("TRUE"),
+            expires: fields[4].parse().ok()?,
+            name: fields[5].to_string(),
+            value: fields[6].to_string(),
+        })
+    }
+
+    fn matches(&self, uri: &Uri, now: u64) -> bool {
+        let host = match uri.host() {
+            Some(host) => host.to_lowercase(),
+            None => return false,
+        };
+        let domain_matches = host == self.domain || (self.include_subdomains
+            && host.ends_with(&format!(".{}", &self.domain)));
+        let is_expired = self.expires != 0 && self.expires <= now;
+        let is_secure = uri.scheme_str() == Some("https");
+        domain_matches
+            && uri.path().starts_with(&self.path)
+            && !is_expired
+            && (is_secure || !self.secure_only)
+    }
+}
@@ -0,0 +1,598 @@
+use std::{
+    cell::{RefCell},
+    io::{Read},
+    path::{PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use encoding_rs::{Encoding, UTF_8};
+use isahc::{
+    Body, Error as IsahcError, HttpClient, HttpClientBuilder,
+    config::{ClientCertificate, DnsCache, PrivateKey, RedirectPolicy},
+    http::{
+        Uri,
+        header::{CONTENT_TYPE, COOKIE, LOCATION},
+    },
+    prelude::*,
+};
+use kuchiki::{
+    NodeRef,
+    traits::*,
+};
+use serde::{Deserialize};
+
+use crate::{
+    NovelError, NovelResult, RunInfo,
+    cookie_jar::{CookieJar},
+    novel::{novel_utils},
+    request_limiter::{HostBackoff, HostOutages, RequestLimiter},
+};
+
+// Enough to go from http to https or follow a moved page, but not enough to get stuck in a loop
+const MAX_REDIRECTS: u32 = 5;
+
+// Everything that every request needs to share, no matter which novel it's for
+pub struct Fetcher {
+    client: HttpClient,
+    request_limiter: Option<RequestLimiter>,
+    host_backoff: Option<HostBackoff>,
+    host_outages: Option<HostOutages>,
+    retry: RetrySchedule,
+    connect_retry: RetrySchedule,
+    // Only for the novel that's being made right now
+    retry_budget: Mutex< Option<Arc<RetryBudget>> >,
+    unsafe_ssl_hosts: Vec<String>,
+    allowed_hosts: Vec<String>,
+    denied_hosts: Vec<String>,
+    min_content_chars: Option<usize>,
+    cookie_jar: Option<CookieJar>,
+    // Nothing new gets fetched after this, so the run can end on time
+    deadline: Option<Instant>,
+}
+impl Fetcher {
+    pub fn new(run_info: &RunInfo) -> NovelResult<Fetcher> {
+        // Redirects get followed by fetch_page_once, so that every hop gets checked first
+        let mut client_builder = run_info.connection_pool.apply(HttpClient::builder()
+            .redirect_policy(RedirectPolicy::None));
+        if let Some(client_certificate) = run_info.client_certificate.as_ref() {
+            client_builder = client_builder.ssl_client_certificate(ClientCertificate::PEM {
+                path: client_certificate.cert_path.clone(),
+                private_key: client_certificate.key_path.clone().map(|key_path| PrivateKey::PEM {
+                    path: key_path,
+                    password: None,
+                }),
+            });
+        }
+        let client = client_builder.build()?;
+        let cookie_jar = match run_info.cookies_path.as_ref() {
+            Some(cookies_path) => Some(CookieJar::load(cookies_path)?),
+            None => None,
+        };
+        Ok(Fetcher {
+            client,
+            request_limiter: run_info.global_requests_per_minute.map(|requests_per_minute| {
+                RequestLimiter::new(requests_per_minute, run_info.request_jitter_percent)
+            }),
+            host_backoff: run_info.adaptive_backoff.map(|backoff_options| {
+                HostBackoff::new(backoff_options, run_info.request_jitter_percent)
+            }),
+            host_outages: run_info.outage_cooldown.map(HostOutages::new),
+            retry: run_info.retry,
+            connect_retry: run_info.connect_retry,
+            retry_budget: Mutex::new(None),
+            unsafe_ssl_hosts: run_info.unsafe_ssl_hosts.clone(),
+            allowed_hosts: run_info.allowed_hosts.clone(),
+            denied_hosts: run_info.denied_hosts.clone(),
+            min_content_chars: run_info.min_content_chars,
+            cookie_jar,
+            deadline: run_info.time_budget_mins.map(|time_budget_mins| {
+                Instant::now() + Duration::from_secs(time_budget_mins * 60)
+            }),
+        })
+    }
+
+    pub fn is_out_of_time(&self) -> bool {
+        self.deadline.map_or(false, |deadline| Instant::now() >= deadline)
+    }
+
+    // Every fetch after this counts against the new budget, until the next one starts
+    pub fn start_retry_budget(&self, max_total_retries: Option<u32>) {
+        *self.retry_budget.lock().unwrap() = max_total_retries.map(|max_total_retries| {
+            Arc::new(RetryBudget::new(max_total_retries))
+        });
+    }
+
+    // A short page is fine for a table of contents, so only chapter contents get checked
+    //  The short content gets kept between tries, to compare with what comes back next
+    pub fn check_content_length<T: PartialEq>(&self, uri: &Uri, content: T, char_count: usize,
+    last_short_content: &RefCell< Option<T> >) -> NovelResult<T> {
+        check_content_length(self.min_content_chars, uri, content, char_count,
+            last_short_content)
+    }
+
+    // An empty allow list lets every host through that isn't denied
+    fn is_host_allowed(&self, host: &str) -> bool {
+        let is_allowed = self.allowed_hosts.is_empty() || is_listed_host(host, &self.allowed_hosts);
+        is_allowed && !is_listed_host(host, &self.denied_hosts)
+    }
+
+    pub fn fetch_page(&self, uri: &Uri) -> NovelResult<NodeRef> {
+        self.fetch_page_with(uri, Ok)
+    }
+    // Anything that parse fails with gets retried the same way as a failed request
+    pub fn fetch_page_with<T, F>(&self, uri: &Uri, parse: F) -> NovelResult<T>
+    where F: Fn(NodeRef) -> NovelResult<T> {
+        let host = uri.host().unwrap_or("");
+        if !self.is_host_allowed(host) {
+            println!("Not allowed to fetch from {}, so {} was skipped", host, uri);
+            return Err(NovelError::HostNotAllowed {
+                url: uri.to_string(),
+            });
+        }
+        // Whatever is already being fetched gets to finish, but nothing new starts
+        if self.is_out_of_time() {
+            return Err(NovelError::OutOfTime);
+        }
+        let retry_budget = self.retry_budget.lock().unwrap().clone();
+        // The novel is going to fail anyways, so there's no point in asking for more
+        if retry_budget.as_ref().map_or(false, |retry_budget| retry_budget.is_used_up()) {
+            return Err(NovelError::RetryBudgetUsedUp);
+        }
+        let mut retries = 0;
+        let mut connect_retries = 0;
+        loop {
+            if let Some(host_outages) = self.host_outages.as_ref() {
+                if !host_outages.wait_for_host(host) {
+                    return Err(NovelError::SiteDown {
+                        host: host.to_string(),
+                    });
+                }
+            }
+            if let Some(host_backoff) = self.host_backoff.as_ref() {
+                host_backoff.wait_for_host(host);
+            }
+            let error = match self.fetch_page_once(uri).and_then(&parse) {
+                Ok(parsed) => {
+                    if let Some(host_backoff) = self.host_backoff.as_ref() {
+                        host_backoff.record_success(host);
+                    }
+                    if let Some(host_outages) = self.host_outages.as_ref() {
+                        host_outages.record_success(host);
+                    }
+                    return Ok(parsed);
+                },
+                Err(error) => error,
+            };
+            if let (Some(host_outages), NovelError::ServerError { .. }) =
+                (self.host_outages.as_ref(), &error) {
+                if host_outages.record_server_error(host) {
+                    continue;
+                }
+            }
+            let retry_kind = RetryKind::of(&error);
+            // Anything that would get retried is a sign that the site wants us to slow down
+            match (self.host_backoff.as_ref(), &retry_kind) {
+                (_, RetryKind::Never) | (None, _) => (),
+                (Some(host_backoff), _) => host_backoff.record_failure(host),
+            }
+            let (schedule, used_retries) = match retry_kind {
+                RetryKind::Connect => (&self.connect_retry, &mut connect_retries),
+                RetryKind::Transient => (&self.retry, &mut retries),
+                RetryKind::Never => return Err(error),
+            };
+            if *used_retries >= schedule.attempts {
+                return Err(error);
+            }
+            if let Some(retry_budget) = retry_budget.as_ref() {
+                if !retry_budget.take_retry() {
+                    println!("Giving up on {} since the novel is out of retries", uri);
+                    return Err(NovelError::RetryBudgetUsedUp);
+                }
+            }
+            *used_retries += 1;
+            println!("Retrying {} in {}s after {:?}", uri, schedule.delay_secs, error);
+            thread::sleep(Duration::from_secs(schedule.delay_secs));
+        }
+    }
+
+    fn fetch_page_once(&self, uri: &Uri) -> NovelResult<NodeRef> {
+        if let Some(request_limiter) = self.request_limiter.as_ref() {
+            request_limiter.wait_for_turn();
+        }
+        let (final_uri, mut response) = self.send_following_redirects(uri)?;
+        if &final_uri != uri {
+            println!("Redirected from {} to {}", uri, &final_uri);
+            // Deleted novels tend to send us back to the top page of the site
+            let sent_to_top = final_uri.path() == "/" && uri.path() != "/";
+            if final_uri.host() != uri.host() || sent_to_top {
+                return Err(NovelError::RedirectedAway {
+                    from: uri.to_string(),
+                    to: final_uri.to_string(),
+                });
+            }
+        }
+        // The page would only say that something went wrong, which isn't worth parsing
+        if response.status().is_server_error() {
+            return Err(NovelError::ServerError {
+                url: uri.to_string(),
+                status: response.status().as_u16(),
+            });
+        }
+        let content_type = response.headers().get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(|content_type| content_type.to_string());
+        let mut page_bytes = Vec::new();
+        response.body_mut().read_to_end(&mut page_bytes)?;
+        let page_text = decode_page(&page_bytes, content_type.as_ref().map(|c| c.as_str()));
+        Ok(kuchiki::parse_html().one(page_text))
+    }
+    // curl would send every hop before we got to see where it goes, so each one gets checked
+    //  here instead. Nothing is sent to a hop that the run doesn't allow
+    fn send_following_redirects(&self, uri: &Uri) -> NovelResult<(Uri, Response<Body>)> {
+        let mut hop_uri = uri.clone();
+        let mut redirects = 0;
+        loop {
+            let request = make_request(&hop_uri, self.cookie_jar.as_ref(), &self.unsafe_ssl_hosts)?;
+            let response = self.client.send(request)?;
+            let next_uri = match redirect_location(&response) {
+                Some(location) => novel_utils::resolve_link(&hop_uri, &location)?,
+                None => return Ok((hop_uri, response)),
+            };
+            if redirects >= MAX_REDIRECTS {
+                return Err(IsahcError::TooManyRedirects.into());
+            }
+            redirects += 1;
+            if is_login_path(next_uri.path()) {
+                return Err(login_required(uri));
+            }
+            let next_host = next_uri.host().unwrap_or("");
+            if !self.is_host_allowed(next_host) {
+                println!("Not allowed to fetch from {}, so the redirect from {} to {} was stopped",
+                    next_host, &hop_uri, &next_uri);
+                return Err(NovelError::HostNotAllowed {
+                    url: next_uri.to_string(),
+                });
+            }
+            hop_uri = next_uri;
+        }
+    }
+}
+
+// Every hop gets its own request, since the cookies and the SSL checks depend on the host
+fn make_request(uri: &Uri, cookie_jar: Option<&CookieJar>, unsafe_ssl_hosts: &[String])
+-> NovelResult< Request<()> > {
+    let mut request = Request::get(uri.clone());
+    if skips_ssl_check(uri, unsafe_ssl_hosts) {
+        request.danger_allow_unsafe_ssl(true);
+    }
+    if let Some(cookie_header) = cookie_jar.and_then(|cookie_jar| cookie_jar.cookie_header(uri)) {
+        request.header(COOKIE, cookie_header.as_str());
+    }
+    Ok(request.body(())?)
+}
+// Only for this one hop. A listed host that redirects somewhere else still gets that host checked
+fn skips_ssl_check(uri: &Uri, unsafe_ssl_hosts: &[String]) -> bool {
+    uri.host().map_or(false, |host| is_listed_host(host, unsafe_ssl_hosts))
+}
+// Only when the response is a redirect that says where to go
+fn redirect_location(response: &Response<Body>) -> Option<String> {
+    if !response.status().is_redirection() {
+        return None;
+    }
+    response.headers().get(LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .map(|location| location.trim().to_string())
+        .filter(|location| !location.is_empty())
+}
+
+// Every list of hosts in the config works the same way, with each host covering its subdomains
+fn is_listed_host(host: &str, listed_hosts: &[String]) -> bool {
+    listed_hosts.iter().any(|listed_host| is_same_or_subdomain(host, listed_host))
+}
+// Hosts don't care about case, and a subdomain has to end with a whole part (after a '.')
+fn is_same_or_subdomain(host: &str, listed_host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let listed_host = listed_host.to_ascii_lowercase();
+    host == listed_host || host.ends_with(&format!(".{}", listed_host))
+}
+
+// A busy page changes from one try to the next, but a chapter that really is that short comes
+//  back the same every time. So it only gets kept once it's been seen twice in a row
+fn check_content_length<T: PartialEq>(min_content_chars: Option<usize>, uri: &Uri, content: T,
+char_count: usize, last_short_content: &RefCell< Option<T> >) -> NovelResult<T> {
+    match min_content_chars {
+        Some(min_content_chars) if char_count < min_content_chars => {
+            let is_repeated = last_short_content.borrow().as_ref() == Some(&content);
+            if is_repeated {
+                println!("Warning: Keeping {} with only {} characters, since it came back the \
+                    same after retrying", uri, char_count);
+                Ok(content)
+            } else {
+                *last_short_content.borrow_mut() = Some(content);
+                Err(NovelError::SoftBlocked { char_count })
+            }
+        },
+        _ => Ok(content),
+    }
+}
+
+// Where the sites send us when a page is only for members
+fn is_login_path(path: &str) -> bool {
+    let path = path.to_lowercase();
+    path.contains("login") || path.contains("signin")
+}
+// Nothing else from the novel will work either, so this says how to fix it
+pub fn login_required(uri: &Uri) -> NovelError {
+    println!("{} needs a login. Set cookies_path to the cookies.txt of a logged in browser", uri);
+    NovelError::LoginRequired {
+        url: uri.to_string(),
+    }
+}
+
+// Only PEM files, since that's what a server's own certificates usually are
+#[derive(Debug, Deserialize)]
+pub struct ClientCertificateInfo {
+    cert_path: PathBuf,
+    key_path: Option<PathBuf>,
+}
+
+// Anything that's left out stays the way that isahc has it
+#[derive(Debug, Default, Deserialize)]
+pub struct ConnectionPoolOptions {
+    // Across every host. Requests wait for a free connection once they are all in use
+    max_connections: Option<usize>,
+    max_connections_per_host: Option<usize>,
+    // How many finished connections are kept to be used again. 0 closes every one of them
+    connection_cache_size: Option<usize>,
+    // How often to check that an idle connection is still there
+    tcp_keepalive_secs: Option<u64>,
+    // How long the address of a host is kept. 0 looks it up again every time
+    dns_cache_secs: Option<u64>,
+}
+impl ConnectionPoolOptions {
+    fn apply(&self, mut client_builder: HttpClientBuilder) -> HttpClientBuilder {
+        if let Some(max_connections) = self.max_connections {
+            client_builder = client_builder.max_connections(max_connections);
+        }
+        if let Some(max_connections_per_host) = self.max_connections_per_host {
+            client_builder = client_builder.max_connections_per_host(max_connections_per_host);
+        }
+        if let Some(connection_cache_size) = self.connection_cache_size {
+            client_builder = client_builder.connection_cache_size(connection_cache_size);
+        }
+        if let Some(tcp_keepalive_secs) = self.tcp_keepalive_secs {
+            client_builder = client_builder.tcp_keepalive(Duration::from_secs(tcp_keepalive_secs));
+        }
+        match self.dns_cache_secs {
+            Some(0) => client_builder.dns_cache(DnsCache::Disable),
+            Some(dns_cache_secs) =>
+                client_builder.dns_cache(Duration::from_secs(dns_cache_secs)),
+            None => client_builder,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct RetrySchedule {
+    // How many more times we will try after the first failure
+    attempts: u32,
+    delay_secs: u64,
+}
+impl RetrySchedule {
+    // Timeouts and dropped responses usually clear up quickly
+    pub fn default_retry() -> RetrySchedule {
+        RetrySchedule {
+            attempts: 3,
+            delay_secs: 5,
+        }
+    }
+    // When we can't even connect, the site is probably down for a little while
+    pub fn default_connect_retry() -> RetrySchedule {
+        RetrySchedule {
+            attempts: 2,
+            delay_secs: 60,
+        }
+    }
+}
+// Shared by every fetch for a novel, no matter which thread it's on
+struct RetryBudget {
+    retries_left: AtomicU32,
+    used_up: AtomicBool,
+}
+impl RetryBudget {
+    fn new(max_total_retries: u32) -> RetryBudget {
+        RetryBudget {
+            retries_left: AtomicU32::new(max_total_retries),
+            used_up: AtomicBool::new(false),
+        }
+    }
+    fn is_used_up(&self) -> bool { self.used_up.load(Ordering::SeqCst) }
+    // Once this says no, every other fetch for the novel will stop as well
+    fn take_retry(&self) -> bool {
+        let took_retry = self.retries_left
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1))
+            .is_ok();
+        if !took_retry {
+            self.used_up.store(true, Ordering::SeqCst);
+        }
+        took_retry
+    }
+}
+
+enum RetryKind {
+    Connect,
+    Transient,
+    Never,
+}
+impl RetryKind {
+    fn of(error: &NovelError) -> RetryKind {
+        match error {
+            NovelError::IsahcError(isahc_error) => match isahc_error {
+                IsahcError::ConnectFailed |
+                IsahcError::CouldntResolveHost |
+                IsahcError::CouldntResolveProxy => RetryKind::Connect,
+                IsahcError::Timeout |
+                IsahcError::NoResponse |
+                IsahcError::ResponseBodyError(_) |
+                IsahcError::Io(_) => RetryKind::Transient,
+                _ => RetryKind::Never,
+            },
+            NovelError::IOError(_) => RetryKind::Transient,
+            // Asking again too soon just gets the same page back
+            NovelError::SoftBlocked { .. } => RetryKind::Transient,
+            NovelError::ServerError { .. } => RetryKind::Transient,
+            _ => RetryKind::Never,
+        }
+    }
+}
+
+// Older pages can still be in Shift-JIS or EUC-JP, which would turn into garbage as UTF-8
+//  The header wins over the page itself, and a BOM wins over both
+fn decode_page(page_bytes: &[u8], content_type: Option<&str>) -> String {
+    let header_charset = content_type.and_then(find_charset);
+    let meta_charset = || {
+        // The <meta> tag has to show up near the top of the page to count
+        let page_start = String::from_utf8_lossy(&page_bytes[..page_bytes.len().min(1024)])
+            .to_string();
+        find_charset(&page_start)
+    };
+    let encoding = header_charset.or_else(meta_charset)
+        .and_then(|charset| Encoding::for_label(charset.as_bytes()))
+        .unwrap_or(UTF_8);
+    let (page_text, used_encoding, had_errors) = encoding.decode(page_bytes);
+    if had_errors {
+        println!("Some of the page couldn't be read as {}", used_encoding.name());
+    }
+    page_text.into_owned()
+}
+// Works for the Content-Type header along with both kinds of <meta> tag
+fn find_charset(text: &str) -> Option<String> {
+    let lowercase_text = text.to_ascii_lowercase();
+    let charset_start = lowercase_text.find("charset=")? + "charset=".len();
+    let charset: String = lowercase_text[charset_start..].chars()
+        .skip_while(|&c| c == '"' || c == '\'')
+        .take_while(|&c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        .collect();
+    if charset.is_empty() {
+        None
+    } else {
+        Some(charset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(listed_hosts: &[&str]) -> Vec<String> {
+        listed_hosts.iter().map(|listed_host| listed_host.to_string()).collect()
+    }
+
+    #[test]
+    fn a_short_chapter_is_kept_once_it_comes_back_the_same() {
+        let uri: Uri = "https://ncode.syosetu.com/n1234ab/1/".parse().unwrap();
+        let last_short_content = RefCell::new(None);
+        let check = |content: &str| check_content_length(Some(10), &uri, content.to_string(),
+            content.chars().count(), &last_short_content);
+        assert!(matches!(check("混雑中"), Err(NovelError::SoftBlocked { char_count: 3 })));
+        // Still short, but not the same as last time
+        assert!(matches!(check("混雑しています"), Err(NovelError::SoftBlocked { .. })));
+        assert_eq!(check("混雑しています").ok(), Some("混雑しています".to_string()));
+        assert_eq!(check("とても長い本文がここにあります").ok(),
+            Some("とても長い本文がここにあります".to_string()));
+    }
+    #[test]
+    fn nothing_is_too_short_without_a_minimum() {
+        let uri: Uri = "https://ncode.syosetu.com/n1234ab/1/".parse().unwrap();
+        let last_short_content = RefCell::new(None);
+        assert_eq!(check_content_length(None, &uri, "短い", 2, &last_short_content).ok(),
+            Some("短い"));
+        assert_eq!(*last_short_content.borrow(), None);
+    }
+    #[test]
+    fn listed_hosts_cover_their_subdomains() {
+        let listed_hosts = hosts(&["home.lan", "syosetu.com"]);
+        assert!(is_listed_host("home.lan", &listed_hosts));
+        assert!(is_listed_host("archive.home.lan", &listed_hosts));
+        assert!(is_listed_host("NCODE.Syosetu.com", &listed_hosts));
+        assert!(!is_listed_host("kakuyomu.jp", &listed_hosts));
+        // Only a whole part counts
+        assert!(!is_listed_host("myhome.lan", &listed_hosts));
+        assert!(!is_listed_host("home.lan.example.com", &listed_hosts));
+    }
+    #[test]
+    fn an_empty_list_has_no_hosts() {
+        assert!(!is_listed_host("syosetu.com", &[]));
+    }
+
+    fn cookie_jar(cookies_text: &str) -> CookieJar {
+        let cookies_path = std::env::temp_dir().join(format!("fetcher_cookies_{}.txt",
+            std::process::id()));
+        std::fs::write(&cookies_path, cookies_text).unwrap();
+        let cookie_jar = CookieJar::load(&cookies_path).expect("The cookies couldn't be loaded");
+        std::fs::remove_file(&cookies_path).unwrap();
+        cookie_jar
+    }
+    fn cookie_of(request: &Request<()>) -> Option<&str> {
+        request.headers().get(COOKIE).map(|cookie| cookie.to_str().unwrap())
+    }
+
+    #[test]
+    fn cookies_stay_with_their_own_host_across_a_redirect() {
+        let cookie_jar = cookie_jar(".kakuyomu.jp\tTRUE\t/\tTRUE\t0\tsession\tlogged-in\n");
+        let first_uri: Uri = "https://kakuyomu.jp/works/1".parse().unwrap();
+        let first_request = make_request(&first_uri, Some(&cookie_jar), &[]).unwrap();
+        assert_eq!(cookie_of(&first_request), Some("session=logged-in"));
+
+        // Like a moved novel that now lives somewhere else
+        let redirect = response(302, Some("https://example.com/works/1"));
+        let next_uri = novel_utils::resolve_link(&first_uri,
+            &redirect_location(&redirect).unwrap()).unwrap();
+        let next_request = make_request(&next_uri, Some(&cookie_jar), &[]).unwrap();
+        assert_eq!(cookie_of(&next_request), None);
+
+        // Coming back to the novel's own host gets the cookies again
+        let back_uri: Uri = "https://kakuyomu.jp/works/2".parse().unwrap();
+        let back_request = make_request(&back_uri, Some(&cookie_jar), &[]).unwrap();
+        assert_eq!(cookie_of(&back_request), Some("session=logged-in"));
+    }
+
+    #[test]
+    fn only_hops_to_unsafe_ssl_hosts_skip_the_ssl_check() {
+        let unsafe_ssl_hosts = hosts(&["home.lan"]);
+        let first_uri: Uri = "https://archive.home.lan/works/1".parse().unwrap();
+        assert!(skips_ssl_check(&first_uri, &unsafe_ssl_hosts));
+
+        let redirect = response(301, Some("https://kakuyomu.jp/works/1"));
+        let next_uri = novel_utils::resolve_link(&first_uri,
+            &redirect_location(&redirect).unwrap()).unwrap();
+        assert!(!skips_ssl_check(&next_uri, &unsafe_ssl_hosts));
+
+        let redirect = response(301, Some("/works/2"));
+        let same_host_uri = novel_utils::resolve_link(&first_uri,
+            &redirect_location(&redirect).unwrap()).unwrap();
+        assert!(skips_ssl_check(&same_host_uri, &unsafe_ssl_hosts));
+    }
+
+    fn response(status: u16, location: Option<&str>) -> Response<Body> {
+        let mut response = Response::builder();
+        response.status(status);
+        if let Some(location) = location {
+            response.header(LOCATION, location);
+        }
+        response.body(Body::empty()).unwrap()
+    }
+    #[test]
+    fn only_redirects_have_somewhere_to_go() {
+        assert_eq!(redirect_location(&response(301, Some("https://kakuyomu.jp/works/1"))),
+            Some("https://kakuyomu.jp/works/1".to_string()));
+        assert_eq!(redirect_location(&response(302, Some("/n1234ab/"))),
+            Some("/n1234ab/".to_string()));
+        assert_eq!(redirect_location(&response(302, None)), None);
+        assert_eq!(redirect_location(&response(200, Some("/n1234ab/"))), None);
+    }
+}
@@ -0,0 +1,19 @@
+use crate::{RunInfo};
+
+// Gives back true only when every novel could be reached
+pub fn check_novels(run_info: &RunInfo) -> bool {
+    let novels = run_info.novel_batch();
+    let mut failed_count = 0;
+    for novel_info in novels.iter() {
+        match crate::fetch_novel_title(novel_info) {
+            Ok(title) => println!("OK   {}: {}", &novel_info.short_name, title),
+            Err(e) => {
+                println!("FAIL {} ({}): {:?}", &novel_info.short_name, &novel_info.url, e);
+                failed_count += 1;
+            },
+        }
+    }
+    println!("{} of {} novels are reachable",
+        novels.len() - failed_count, novels.len());
+    failed_count == 0
+}
@@ -0,0 +1,846 @@
+mod build_diff;
+mod cookie_jar;
+mod fetcher;
+mod health_check;
+// Every site's parse functions only need a page that's already been fetched, so a saved page
+//  works just as well as one straight from the site
+pub mod novel;
+mod probe;
+pub mod progress;
+mod request_limiter;
+mod run_report;
+mod status_check;
+pub mod traverser;
+
+use std::{
+    collections::{HashSet},
+    fs,
+    io::{Error as IOError},
+    path::{Path, PathBuf},
+    process::{Command},
+    sync::{OnceLock},
+    time::{Instant},
+};
+use isahc::{
+    Error as IsahcError,
+    http::{
+        Error as HttpError, Uri,
+        uri::InvalidUri,
+    },
+};
+use kuchiki::{NodeRef};
+use serde::{Deserialize};
+use zip::result::{ZipError};
+
+use ebook_builder::{
+    BookError,
+    xml_tree::{XmlError},
+};
+
+use crate::{
+    fetcher::{ClientCertificateInfo, ConnectionPoolOptions, Fetcher, RetrySchedule},
+    novel::{
+        BookType, Novel, NovelMetadata, NovelOverview, NovelSite, NovelStatus, OutputValidation,
+        ReadingDirection, SectionKey, SelectorOverrides, TitlePageOptions, VolumeStrategy,
+        epub::{XhtmlFormat},
+        post_process::{PostProcessPipeline, PostProcessStep},
+    },
+    progress::{ProgressObserver},
+    request_limiter::{BackoffOptions, OutageOptions},
+    run_report::{RunReport},
+    status_check::{StatusRecords},
+    traverser::{TraverseError},
+};
+
+#[derive(Deserialize)]
+pub struct RunInfo {
+    save_dir: PathBuf,
+    // Only "epub" can be made right now
+    #[serde(default)]
+    ebook_type: BookType,
+    // How the sections of a novel get split into separate books
+    #[serde(default)]
+    volume_strategy: VolumeStrategy,
+    // Remembers which chapters went into each volume, so that they keep the same numbers
+    //  (and chapters) as the novel keeps going
+    #[serde(default)]
+    stable_volume_numbers: bool,
+    // The program (and any arguments) to run with the path of every saved book
+    post_build_command: Option< Vec<String> >,
+    // Puts the labels from the table of contents (like warnings) under each chapter's name
+    #[serde(default)]
+    show_chapter_tags: bool,
+    // Writes a JSON list of every chapter (and where it came from) next to each book
+    #[serde(default)]
+    write_manifest: bool,
+    // Writes a JSON list of each chapter's page inside of the book, for linking right to it
+    #[serde(default)]
+    write_chapter_index: bool,
+    // Also writes every chapter as Markdown, in a folder named after its book
+    #[serde(default)]
+    export_markdown: bool,
+    // Also writes every book as a text file with Aozora Bunko's notation (like ｜漢字《かんじ》)
+    #[serde(default)]
+    export_aozora: bool,
+    // Adds a page at the end of every book with where and when it came from
+    #[serde(default)]
+    include_colophon: bool,
+    // Skips every novel that isn't finished, or that was already made after it finished
+    #[serde(default)]
+    only_finished: bool,
+    // Saves each novel's books in a folder for its status, like 連載中 and 完結済
+    //  Omnibuses still go right into the save_dir, since their novels can be in both
+    #[serde(default)]
+    group_by_status: bool,
+    // Makes a separate book for every chapter instead of for each section
+    #[serde(default)]
+    one_epub_per_chapter: bool,
+    // Only makes a small book with the title page and the list of chapters, without fetching
+    //  any of them. Quick enough to look through a lot of novels before making them for real
+    #[serde(default)]
+    stub_only: bool,
+    // Drops the blank lines at the start and end of every chapter, but keeps the ones in between
+    #[serde(default)]
+    trim_blank_edges: bool,
+    // Changes the text of every chapter, one after the other, before it goes into a book
+    #[serde(default)]
+    post_processors: Vec<PostProcessStep>,
+    // What goes on the title page at the start of every book, in order
+    #[serde(default)]
+    title_page: TitlePageOptions,
+    // Leaves the title page out, so that every book starts right at its first page
+    //  An omnibus still gets one for each novel, since they keep the novels apart
+    #[serde(default)]
+    skip_title_page: bool,
+    // Puts the author's notes from before and after each chapter (like 前書き) around it
+    #[serde(default)]
+    include_author_notes: bool,
+    // Puts each chapter's date after its name in the book's navigation
+    #[serde(default)]
+    nav_include_date: bool,
+    // Adds a contents page to the end of every book, with a link back to it after each chapter
+    #[serde(default)]
+    chapter_toc_link: bool,
+    // "as_is" (the default), "pretty" to read or diff the pages, or "minify" to make them smaller
+    #[serde(default)]
+    xhtml_format: XhtmlFormat,
+    // Checks that every chapter still has some text once its page is made
+    #[serde(default)]
+    validate_output: OutputValidation,
+    // Prints every link inside of a saved book that doesn't go to one of its files
+    #[serde(default)]
+    verify_links: bool,
+    // A font to put in every book for the text, instead of whatever the device has
+    font_path: Option<PathBuf>,
+    // Gets its own page between each of the sections that are in the same book, like ◇◇◇
+    section_separator: Option<String>,
+    // The name of each book that's made out of sections. See novel_info.toml for the placeholders
+    #[serde(default = "default_volume_name_format")]
+    volume_name_format: String,
+    // Adds " (完)" to the name of a finished novel's last book (or {kan_stamp} in the format)
+    #[serde(default = "default_include_kan_stamp")]
+    include_kan_stamp: bool,
+    // Shown above the name on each section's cover, with {num} as the section's number
+    #[serde(default = "default_section_label")]
+    section_label: String,
+    // Only works on part of the novel list, so that it can be split up over many runs
+    #[serde(default)]
+    novel_offset: usize,
+    novel_limit: Option<usize>,
+    // Stops making novels after this long. The novel that was still going gets left out
+    time_budget_mins: Option<u64>,
+    // A cap on every request we make, across all of the novels and sites
+    global_requests_per_minute: Option<u32>,
+    // Chapters shorter than this (in characters) are taken as a "too many requests" page
+    //  that still looks like a chapter, so they get retried like a timeout would
+    //  One that comes back the same after a retry really is that short, so it gets kept
+    min_content_chars: Option<usize>,
+    // Moves each wait between requests up or down by up to this percent, at random
+    #[serde(default)]
+    request_jitter_percent: u32,
+    // Waits longer between requests to a host after it keeps failing, like when it's throttling us
+    adaptive_backoff: Option<BackoffOptions>,
+    // Takes a long break from a host that keeps sending back server errors (like 503), and then
+    //  gives up on it for the rest of the run
+    outage_cooldown: Option<OutageOptions>,
+    // For timeouts and other failures partway through a request
+    #[serde(default = "RetrySchedule::default_retry")]
+    retry: RetrySchedule,
+    // For when we can't even reach the site, which needs a longer wait before trying again
+    #[serde(default = "RetrySchedule::default_connect_retry")]
+    connect_retry: RetrySchedule,
+    // Hosts (and their subdomains) that skip checking their certificate, like an archive server
+    //  with a self-signed one. Every other host always gets checked
+    #[serde(default)]
+    unsafe_ssl_hosts: Vec<String>,
+    // When there are any, only these hosts (and their subdomains) will ever get a request
+    #[serde(default)]
+    allowed_hosts: Vec<String>,
+    // These hosts (and their subdomains) never get a request, even when they are allowed
+    #[serde(default)]
+    denied_hosts: Vec<String>,
+    // Only for big runs that need to tune how connections get opened and kept
+    #[serde(default)]
+    connection_pool: ConnectionPoolOptions,
+    // Sent to servers that ask for one
+    client_certificate: Option<ClientCertificateInfo>,
+    // A cookies.txt file exported from a browser, for sites that need us to be logged in
+    cookies_path: Option<PathBuf>,
+    novels: Vec<NovelInfo>,
+    // Every work in each of these lists (like a Kakuyomu collection) gets made like a novel
+    //  Works that are already in the novels only get made once
+    #[serde(default)]
+    collections: Vec<String>,
+    // Each one puts several novels together into a single book
+    #[serde(default)]
+    omnibuses: Vec<OmnibusInfo>,
+}
+fn default_section_label() -> String { "第{num}章".to_string() }
+fn default_volume_name_format() -> String {
+    "{title} {volume_num} 「{volume_name}」 [{author}] (投稿版) ({first_chapter}部分-{last_chapter}部分)\
+        {kan_stamp}".to_string()
+}
+fn default_include_kan_stamp() -> bool { true }
+fn default_fallback_chapter_name() -> String { "第{num}話".to_string() }
+impl RunInfo {
+    fn novel_batch(&self) -> &[NovelInfo] {
+        let start = self.novel_offset.min(self.novels.len());
+        let end = match self.novel_limit {
+            Some(limit) => start.saturating_add(limit).min(self.novels.len()),
+            None => self.novels.len(),
+        };
+        &self.novels[start..end]
+    }
+}
+#[derive(Deserialize)]
+pub struct NovelInfo {
+    // Not needed for local novels
+    #[serde(default)]
+    url: String,
+    short_name: String,
+    // A folder of text files (one for each chapter) to use instead of a site
+    local_dir: Option<PathBuf>,
+    // Only needed when this novel's layout doesn't match the rest of its site
+    #[serde(default)]
+    selectors: SelectorOverrides,
+    // Chapters with any of these tags get left out completely. Only kakuyomu has tags
+    #[serde(default)]
+    exclude_tags: Vec<String>,
+    // Chapters with any of these in their name (or as one of their tags) get moved to a 付録
+    //  section at the end, like 設定資料 or 登場人物紹介
+    #[serde(default)]
+    appendix_patterns: Vec<String>,
+    // Moves these sections to the front, for when the table of contents has them out of order
+    #[serde(default)]
+    section_order: Vec<SectionKey>,
+    // Used when a chapter's name is empty, with {num} as the chapter's number
+    #[serde(default = "default_fallback_chapter_name")]
+    fallback_chapter_name: String,
+    // Only keeps this many of the newest chapters, leaving out every section before them
+    latest: Option<usize>,
+    // Leaves out the sections that don't have any chapters yet, instead of failing the novel
+    //  Like when the author has started the next arc, but hasn't posted anything in it
+    #[serde(default)]
+    drop_empty_sections: bool,
+    // Forces the reading direction, like ltr for a translated novel
+    #[serde(default)]
+    reading_dir: ReadingDirection,
+    // Goes after the number at the top of each chapter, instead of what the site uses
+    chapter_counter: Option<String>,
+    // Stops the novel after this many retries across all of its pages
+    max_total_retries: Option<u32>,
+    // Used instead of what the site has, for when it's full of extra labels like 【完結】
+    title_override: Option<String>,
+    author_override: Option<String>,
+}
+
+impl NovelInfo {
+    // Everything else is left the same as when it's left out of the config
+    pub fn from_url(url: String) -> NovelInfo {
+        // The last part of the URL is the novel's ID on every site
+        let short_name = url.trim_end_matches('/').rsplit('/').next().unwrap_or("").to_string();
+        NovelInfo {
+            url,
+            short_name,
+            local_dir: None,
+            selectors: SelectorOverrides::default(),
+            exclude_tags: Vec::new(),
+            appendix_patterns: Vec::new(),
+            section_order: Vec::new(),
+            fallback_chapter_name: default_fallback_chapter_name(),
+            latest: None,
+            drop_empty_sections: false,
+            reading_dir: ReadingDirection::default(),
+            chapter_counter: None,
+            max_total_retries: None,
+            title_override: None,
+            author_override: None,
+        }
+    }
+}
+
+// Like a series that the site has split into separate novels
+#[derive(Deserialize)]
+pub struct OmnibusInfo {
+    title: String,
+    novels: Vec<NovelInfo>,
+}
+
+// Gives back whether it worked, so that a scheduled run can tell when something went wrong
+//  The args are everything after the program's name, like ["probe", url, selector]
+//  The observer hears about each novel that gets made (the command line uses LoggingObserver)
+pub fn run_command(run_info: &RunInfo, args: &[String], observer: &dyn ProgressObserver)
+-> bool {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("status") => status_check::check_statuses(run_info),
+        Some("check") => return health_check::check_novels(run_info),
+        Some("diff") => build_diff::diff_novels(run_info),
+        Some("metadata") => print_novel_metadata(run_info),
+        Some("retry-failed") => match RunReport::load(&run_info.save_dir) {
+            Ok(last_report) => {
+                println!("Retrying {} novels that failed last time", last_report.failed_count());
+                build_novels(run_info, Some(&last_report), observer);
+            },
+            Err(e) => {
+                println!("Couldn't read the report from the last run: {:?}", e);
+                return false;
+            },
+        },
+        Some("probe") => match (args.get(1), args.get(2)) {
+            (Some(url), Some(selector)) => if let Err(e) = probe::probe_selector(url, selector) {
+                println!("Failed to probe {}: {:?}", url, e);
+                return false;
+            },
+            _ => println!("Use probe <url> <selector>"),
+        },
+        Some(unknown) => println!(
+            "Unknown command {}. Use status, check, diff, metadata, probe, retry-failed or \
+            nothing at all", unknown),
+        None => build_novels(run_info, None, observer),
+    }
+    true
+}
+
+pub const RUN_INFO_PATH: &'static str = "novel_info.toml";
+// Finds every problem at once so that they can all be fixed before we do any real work
+pub fn load_run_info(info_path: &str) -> Result<RunInfo, Vec<String>> {
+    let info_text = fs::read_to_string(info_path)
+        .map_err(|e| vec![format!("Couldn't read it: {}", e)])?;
+    // Notepad likes to save with a BOM at the start
+    let info_text = info_text.trim_start_matches(BOM);
+    // The toml error already says which line it's on
+    let mut run_info: RunInfo = toml::from_str(info_text)
+        .map_err(|e| vec![e.to_string()])?;
+    clean_novel_infos(&mut run_info);
+
+    let problems = validate_novels(&run_info, info_text);
+    if problems.is_empty() {
+        Ok(run_info)
+    } else {
+        Err(problems)
+    }
+}
+
+const BOM: char = '\u{feff}';
+// Pasted URLs and names can come with spaces (or a BOM) around them, which would make
+//  a URL that looks fine fail to parse
+fn clean_novel_infos(run_info: &mut RunInfo) {
+    let omnibus_novels = run_info.omnibuses.iter_mut()
+        .flat_map(|omnibus| omnibus.novels.iter_mut());
+    for novel_info in run_info.novels.iter_mut().chain(omnibus_novels) {
+        novel_info.url = clean_value(&novel_info.url);
+        novel_info.short_name = clean_value(&novel_info.short_name);
+    }
+}
+fn clean_value(value: &str) -> String {
+    value.trim_matches(|c: char| c == BOM || c.is_whitespace()).to_string()
+}
+
+// Gives back the problems that have to be fixed, but only prints out warnings
+fn validate_novels(run_info: &RunInfo, info_text: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut short_names = HashSet::new();
+    let mut urls = HashSet::new();
+    let omnibus_novels = run_info.omnibuses.iter().flat_map(|omnibus| omnibus.novels.iter());
+    for (i, novel_info) in run_info.novels.iter().chain(omnibus_novels).enumerate() {
+        let url_line = info_text.lines().position(|line| {
+            !line.trim_start().starts_with('#') && line.contains(&novel_info.url)
+        });
+        let location = match url_line {
+            Some(line_index) if !novel_info.url.is_empty() =>
+                format!("Novel {} (line {})", i + 1, line_index + 1),
+            _ => format!("Novel {}", i + 1),
+        };
+        if novel_info.short_name.trim().is_empty() {
+            problems.push(format!("{}: The short_name is empty", location));
+        }
+        match novel_info.local_dir.as_ref() {
+            Some(local_dir) => if !local_dir.is_dir() {
+                problems.push(format!("{}: {:?} isn't a folder", location, local_dir));
+            },
+            None => match novel_info.url.parse::<Uri>() {
+                Ok(uri) => if NovelSite::is_a_novel(&uri).is_none() {
+                    problems.push(format!("{}: {} isn't from a site we know", location,
+                        &novel_info.url));
+                },
+                Err(e) => problems.push(format!(
+                    "{}: {:?} isn't a URL, even without the spaces around it ({})", location,
+                    &novel_info.url, e)),
+            },
+        }
+
+        // Only kakuyomu's table of contents has tags, so they can't match anything else
+        let has_tags = novel_info.local_dir.is_none() && novel_info.url.parse::<Uri>().ok()
+            .and_then(|uri| NovelSite::is_a_novel(&uri))
+            .map_or(false, |novel_site| match novel_site {
+                NovelSite::Kakuyomu => true,
+                NovelSite::Syosetu => false,
+            });
+        if !novel_info.exclude_tags.is_empty() && !has_tags {
+            println!("Warning: {}: exclude_tags only works for kakuyomu, so it will be ignored",
+                location);
+        }
+        // Doing the same novel twice only wastes time, so it isn't worth stopping for
+        if !short_names.insert(&novel_info.short_name) {
+            println!("Warning: {}: The short_name {} is used more than once", location,
+                &novel_info.short_name);
+        }
+        if !novel_info.url.is_empty() && !urls.insert(&novel_info.url) {
+            println!("Warning: {}: {} is used more than once", location, &novel_info.url);
+        }
+    }
+    problems
+}
+
+// With the report from the last run, only the novels (and omnibuses) that failed get made
+//  Every novel in the config gets looked at then, not only the ones in this batch
+fn build_novels(run_info: &RunInfo, last_report: Option<&RunReport>,
+observer: &dyn ProgressObserver) {
+    let (configured_novels, collection_novels) = match last_report {
+        Some(last_report) => (&run_info.novels[..], last_report.failed_unlisted_novels(run_info)),
+        None => (run_info.novel_batch(), fetch_collection_novels(run_info)),
+    };
+    // Checking the statuses has its own records, so only_finished only goes by what was built
+    let mut built_records = StatusRecords::load_built(&run_info.save_dir);
+    let mut run_report = RunReport::default();
+    let mut skipped_novels = Vec::new();
+    let pipeline = PostProcessPipeline::new(run_info);
+    for novel_info in configured_novels.iter().chain(collection_novels.iter()) {
+        if last_report.map_or(false, |last_report| !last_report.has_failed(novel_info)) {
+            continue;
+        }
+        if fetcher().is_out_of_time() {
+            skipped_novels.push(novel_info.short_name.as_str());
+            run_report.record_failed(novel_info, &NovelError::OutOfTime);
+            continue;
+        }
+        if run_info.only_finished && !is_newly_finished(novel_info, &built_records) {
+            continue;
+        }
+        if run_info.stub_only {
+            match build_stub_novel(novel_info, run_info) {
+                Ok(()) => run_report.record_finished(novel_info),
+                Err(e) => {
+                    observer.on_error(&novel_info.short_name, &e);
+                    run_report.record_failed(novel_info, &e);
+                },
+            }
+            continue;
+        }
+        observer.on_novel_started(&novel_info.short_name);
+        let start = Instant::now();
+        fetcher().start_retry_budget(novel_info.max_total_retries);
+
+        let mut novel = match fetch_novel(novel_info, observer) {
+            Ok(novel) => novel,
+            Err(NovelError::OutOfTime) => {
+                skipped_novels.push(novel_info.short_name.as_str());
+                run_report.record_failed(novel_info, &NovelError::OutOfTime);
+                continue;
+            },
+            Err(e) => {
+                observer.on_error(&novel_info.short_name, &e);
+                run_report.record_failed(novel_info, &e);
+                continue;
+            },
+        };
+        novel.post_process(&pipeline);
+        match novel.save_epubs(run_info) {
+            Err(e) => {
+                observer.on_error(&novel_info.short_name, &e);
+                run_report.record_failed(novel_info, &e);
+                continue;
+            },
+            _ => (),
+        }
+        built_records.record(&novel_info.short_name, novel.status(), novel.chapter_count());
+        run_report.record_finished(novel_info);
+        observer.on_novel_finished(&novel_info.short_name, &novel, start.elapsed());
+    }
+    for omnibus_info in run_info.omnibuses.iter() {
+        let title = omnibus_info.title.as_str();
+        if last_report.map_or(false, |last_report| !last_report.has_failed_omnibus(title)) {
+            continue;
+        }
+        if fetcher().is_out_of_time() {
+            skipped_novels.push(title);
+            run_report.record_failed_omnibus(title);
+            continue;
+        }
+        println!("Starting the omnibus {}", &omnibus_info.title);
+        let start = Instant::now();
+        let novels = match fetch_omnibus_novels(omnibus_info, &pipeline, observer) {
+            Ok(novels) => novels,
+            Err(NovelError::OutOfTime) => {
+                skipped_novels.push(title);
+                run_report.record_failed_omnibus(title);
+                continue;
+            },
+            Err(e) => {
+                println!("Failed the omnibus {}: {:?}", title, e);
+                run_report.record_failed_omnibus(title);
+                continue;
+            },
+        };
+        match novel::save_omnibus(omnibus_info, &novels, run_info) {
+            Ok(()) => println!("Finished the omnibus {} in {:?}", title, start.elapsed()),
+            Err(e) => {
+                println!("Failed to save the omnibus {}: {:?}", title, e);
+                run_report.record_failed_omnibus(title);
+            },
+        }
+    }
+    if !skipped_novels.is_empty() {
+        println!("Ran out of time before finishing {}", skipped_novels.join(", "));
+    }
+    if let Err(e) = built_records.save_built(&run_info.save_dir) {
+        println!("Failed to save the statuses: {:?}", e);
+    }
+    if let Err(e) = run_report.save(&run_info.save_dir) {
+        println!("Failed to save the report: {:?}", e);
+    }
+}
+
+pub type NovelResult<T> = Result<T, NovelError>;
+#[derive(Debug)]
+pub enum NovelError {
+    NotANovel,
+    ComponentMissing(NovelComponent),
+    // The page we asked for sent us somewhere that isn't the novel anymore
+    RedirectedAway {
+        from: String,
+        to: String,
+    },
+    // The site wants us to log in before it shows us the novel
+    LoginRequired {
+        url: String,
+    },
+    // These chapters didn't have any text left once their pages were made
+    EmptyChapters {
+        order_nums: Vec<u32>,
+    },
+    // Like a 503 while the site is down for maintenance
+    ServerError {
+        url: String,
+        status: u16,
+    },
+    // The host kept sending back server errors, even after waiting for it
+    SiteDown {
+        host: String,
+    },
+    // The host isn't allowed by the run's allowed_hosts and denied_hosts
+    HostNotAllowed {
+        url: String,
+    },
+    // The novel used up all of the retries it was allowed
+    RetryBudgetUsedUp,
+    // The time budget for the whole run ran out partway through
+    OutOfTime,
+    // The page came back, but it was too short to be the real thing
+    SoftBlocked {
+        char_count: usize,
+    },
+
+    BookError(BookError),
+    HttpError(HttpError),
+    InvalidUri(InvalidUri),
+    IOError(IOError),
+    IsahcError(IsahcError),
+    JsonError(serde_json::Error),
+    TomlSerError(toml::ser::Error),
+    TraverseError(TraverseError),
+    XmlError(XmlError),
+    ZipError(ZipError),
+}
+impl From<BookError> for NovelError {
+    fn from(error: BookError) -> Self { Self::BookError(error) }
+}
+impl From<HttpError> for NovelError {
+    fn from(error: HttpError) -> Self { Self::HttpError(error) }
+}
+impl From<InvalidUri> for NovelError {
+    fn from(error: InvalidUri) -> Self { Self::InvalidUri(error) }
+}
+impl From<IOError> for NovelError {
+    fn from(error: IOError) -> Self { Self::IOError(error) }
+}
+impl From<IsahcError> for NovelError {
+    fn from(error: IsahcError) -> Self { Self::IsahcError(error) }
+}
+impl From<serde_json::Error> for NovelError {
+    fn from(error: serde_json::Error) -> Self { Self::JsonError(error) }
+}
+impl From<toml::ser::Error> for NovelError {
+    fn from(error: toml::ser::Error) -> Self { Self::TomlSerError(error) }
+}
+impl From<TraverseError> for NovelError {
+    fn from(error: TraverseError) -> Self { Self::TraverseError(error) }
+}
+impl From<XmlError> for NovelError {
+    fn from(error: XmlError) -> Self { Self::XmlError(error) }
+}
+impl From<ZipError> for NovelError {
+    fn from(error: ZipError) -> Self { Self::ZipError(error) }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum NovelComponent {
+    Title,
+    Author,
+    Date,
+    InfoPath,
+    Status,
+    Chapter,
+    ChapterContent,
+    ChapterUnderSection,
+}
+
+// Only the main page gets fetched, so the rest of the novel can be skipped cheaply
+//  A novel that was already made after it finished doesn't need to be made again
+fn is_newly_finished(novel_info: &NovelInfo, built_records: &StatusRecords) -> bool {
+    match fetch_novel_overview(novel_info) {
+        Ok(overview) => overview.status == NovelStatus::Finished
+            && !built_records.is_unchanged(&novel_info.short_name, overview.status,
+                overview.chapter_count),
+        Err(e) => {
+            println!("Failed to check {}: {:?}", &novel_info.short_name, e);
+            false
+        },
+    }
+}
+
+// Every novel has to be there, or else part of the series would be missing from the book
+fn fetch_omnibus_novels(omnibus_info: &OmnibusInfo, pipeline: &PostProcessPipeline,
+observer: &dyn ProgressObserver) -> NovelResult< Vec<Novel> > {
+    let mut novels = Vec::new();
+    for novel_info in omnibus_info.novels.iter() {
+        println!("Starting {} for {}", &novel_info.short_name, &omnibus_info.title);
+        fetcher().start_retry_budget(novel_info.max_total_retries);
+        let mut novel = fetch_novel(novel_info, observer)?;
+        novel.post_process(pipeline);
+        novels.push(novel);
+    }
+    Ok(novels)
+}
+
+// Every novel in the collections that isn't one of the novels already
+fn fetch_collection_novels(run_info: &RunInfo) -> Vec<NovelInfo> {
+    let url_key = |url: &str| url.trim_end_matches('/').to_string();
+    let mut known_urls: HashSet<String> = run_info.novels.iter()
+        .map(|novel_info| url_key(&novel_info.url))
+        .collect();
+    let mut novel_infos = Vec::new();
+    for collection_url in run_info.collections.iter() {
+        let novel_urls = match novel::fetch_collection(collection_url) {
+            Ok(novel_urls) => novel_urls,
+            Err(e) => {
+                println!("Failed to get the collection {}: {:?}", collection_url, e);
+                continue;
+            },
+        };
+        let found_count = novel_urls.len();
+        let new_urls: Vec<String> = novel_urls.into_iter()
+            .filter(|novel_url| known_urls.insert(url_key(novel_url)))
+            .collect();
+        println!("Found {} works in {} ({} of them are new)", found_count, collection_url,
+            new_urls.len());
+        novel_infos.extend(new_urls.into_iter().map(NovelInfo::from_url));
+    }
+    novel_infos
+}
+
+fn fetch_novel(novel_info: &NovelInfo, observer: &dyn ProgressObserver) -> NovelResult<Novel> {
+    if let Some(local_dir) = novel_info.local_dir.as_ref() {
+        return novel::make_local_novel(local_dir, novel_info);
+    }
+    let (uri, novel_site) = find_novel_site(&novel_info.url)?;
+    novel_site.make_novel(uri, novel_info, observer)
+}
+// Doesn't touch the status records, since none of the chapters were made
+fn build_stub_novel(novel_info: &NovelInfo, run_info: &RunInfo) -> NovelResult<()> {
+    if novel_info.local_dir.is_some() {
+        println!("{}: Local novels are already all there, so they don't need a stub",
+            &novel_info.short_name);
+        return Ok(());
+    }
+    let (uri, novel_site) = find_novel_site(&novel_info.url)?;
+    let (novel, chapters) = novel_site.make_stub_novel(uri, novel_info)?;
+    novel.save_stub_epub(&chapters, run_info)?;
+    println!("Made a stub of {} with {} chapters", novel.print_name(), chapters.len());
+    Ok(())
+}
+fn fetch_novel_title(novel_info: &NovelInfo) -> NovelResult<String> {
+    if let Some(local_dir) = novel_info.local_dir.as_ref() {
+        return Ok(novel::fetch_local_overview(local_dir, novel_info)?.title);
+    }
+    let (uri, novel_site) = find_novel_site(&novel_info.url)?;
+    novel_site.fetch_title(uri, novel_info)
+}
+fn fetch_novel_overview(novel_info: &NovelInfo) -> NovelResult<NovelOverview> {
+    if let Some(local_dir) = novel_info.local_dir.as_ref() {
+        return novel::fetch_local_overview(local_dir, novel_info);
+    }
+    let (uri, novel_site) = find_novel_site(&novel_info.url)?;
+    novel_site.fetch_overview(uri, novel_info)
+}
+// Only the main page (and the info page for syosetu), without any of the chapters
+//  Cheap enough to check a whole reading list for updates. Needs set_up_fetcher first
+pub fn fetch_novel_metadata(novel_info: &NovelInfo) -> NovelResult<NovelMetadata> {
+    if let Some(local_dir) = novel_info.local_dir.as_ref() {
+        return novel::fetch_local_metadata(local_dir, novel_info);
+    }
+    let (uri, novel_site) = find_novel_site(&novel_info.url)?;
+    novel_site.fetch_metadata(uri, novel_info)
+}
+// One line of JSON for each novel (starting with {), so another program can pick them out
+fn print_novel_metadata(run_info: &RunInfo) {
+    for novel_info in run_info.novel_batch() {
+        let metadata = fetch_novel_metadata(novel_info)
+            .and_then(|metadata| Ok(serde_json::to_string(&metadata)?));
+        match metadata {
+            Ok(metadata_json) => println!("{}", metadata_json),
+            Err(e) => println!("Failed to get the metadata of {}: {:?}",
+                &novel_info.short_name, e),
+        }
+    }
+}
+fn find_novel_site(novel_url: &str) -> NovelResult<(Uri, NovelSite)> {
+    let uri: Uri = novel_url.parse()?;
+    let novel_site = NovelSite::is_a_novel(&uri)
+        .ok_or(NovelError::NotANovel)?;
+    Ok( (uri, novel_site) )
+}
+
+// Has to be done once before anything gets fetched, since every request goes through it
+static FETCHER: OnceLock<Fetcher> = OnceLock::new();
+pub fn set_up_fetcher(run_info: &RunInfo) -> NovelResult<()> {
+    if FETCHER.set(Fetcher::new(run_info)?).is_err() {
+        println!("Keeping the fetcher that was already set up");
+    }
+    Ok(())
+}
+fn fetcher() -> &'static Fetcher {
+    FETCHER.get().expect("The fetcher needs to be set up before fetching")
+}
+fn fetch_page(uri: &Uri) -> NovelResult<NodeRef> {
+    fetcher().fetch_page(uri)
+}
+fn fetch_page_with<T, F>(uri: &Uri, parse: F) -> NovelResult<T>
+where F: Fn(NodeRef) -> NovelResult<T> {
+    fetcher().fetch_page_with(uri, parse)
+}
+
+// A failing command shouldn't stop the rest of the books from being made
+fn run_post_build_command(command: &[String], book_path: &Path) {
+    let (program, args) = match command.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    match Command::new(program).args(args).arg(book_path).status() {
+        Ok(status) if status.success() => (),
+        Ok(status) => println!("Post build command failed for {:?}: {}", book_path, status),
+        Err(e) => println!("Failed to run the post build command for {:?}: {:?}", book_path, e),
+    }
+}
+
+// Most file systems stop at 255 bytes for a name, which also needs to fit the extension
+const MAX_BOOK_NAME_BYTES: usize = 240;
+fn truncate_book_name(book_name: &str, max_bytes: usize) -> String {
+    if book_name.len() <= max_bytes {
+        return book_name.to_string();
+    }
+    const ELLIPSIS: &'static str = "…";
+    // Cutting in the middle of a character would leave us with invalid UTF-8
+    let mut end = max_bytes.saturating_sub(ELLIPSIS.len());
+    while !book_name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &book_name[..end], ELLIPSIS)
+}
+
+fn sanitize_book_name(book_name: &str) -> String {
+    book_name.chars().map(|c| match c {
+        '?' => '？',
+        '/' => '／',
+        '\\' => '＼',
+        ':' => '：',
+        _ => c,
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        process,
+    };
+
+    use super::*;
+
+    // Light novel titles are often whole sentences, and every character here takes 3 bytes
+    const LONG_TITLE: &'static str = "異世界に転生したら最強の魔法使いになっていたので、のんびりスローライフを\
+        送ろうと思っていたのに、なぜか王国の騎士団長と勇者パーティーと魔王軍の幹部たちに毎日のように\
+        追いかけ回されているんですが、誰か助けてくれませんか？";
+
+    #[test]
+    fn long_japanese_titles_are_cut_on_a_char_boundary() {
+        assert!(LONG_TITLE.len() > MAX_BOOK_NAME_BYTES);
+        let book_name = truncate_book_name(LONG_TITLE, MAX_BOOK_NAME_BYTES);
+        assert!(book_name.len() <= MAX_BOOK_NAME_BYTES);
+        assert!(book_name.ends_with("…"));
+        // Everything except the ellipsis is the start of the title
+        let kept = book_name.trim_end_matches("…");
+        assert!(LONG_TITLE.starts_with(kept));
+        assert!(LONG_TITLE.is_char_boundary(kept.len()));
+        // Nothing more could have fit
+        let next_char_len = LONG_TITLE[kept.len()..].chars().next().unwrap().len_utf8();
+        assert!(kept.len() + next_char_len + "…".len() > MAX_BOOK_NAME_BYTES);
+    }
+    #[test]
+    fn every_cut_point_stays_on_a_char_boundary() {
+        for max_bytes in 0..=LONG_TITLE.len() {
+            let book_name = truncate_book_name(LONG_TITLE, max_bytes);
+            assert!(book_name.len() <= max_bytes.max("…".len()));
+        }
+    }
+    #[test]
+    fn short_titles_are_left_alone() {
+        assert_eq!(truncate_book_name("無職転生", MAX_BOOK_NAME_BYTES), "無職転生");
+    }
+    #[test]
+    fn a_local_novel_has_metadata_without_the_fetcher() {
+        let local_dir = env::temp_dir().join(format!("ローカル小説_{}", process::id()));
+        fs::create_dir_all(&local_dir).unwrap();
+        fs::write(local_dir.join("01_始まり.txt"), "本文").unwrap();
+        let novel_info: NovelInfo = toml::from_str(&format!(
+            "short_name = \"local\"\nlocal_dir = {:?}", local_dir.display().to_string()))
+            .expect("The novel info couldn't be made");
+        let metadata = fetch_novel_metadata(&novel_info);
+        fs::remove_dir_all(&local_dir).unwrap();
+
+        let metadata = metadata.expect("The metadata couldn't be read");
+        assert_eq!(metadata.title, format!("ローカル小説_{}", process::id()));
+        assert_eq!(metadata.chapter_count, 1);
+        assert_eq!(metadata.latest_chapter_date, None);
+    }
+}
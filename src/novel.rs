@@ -1,176 +1,928 @@
-mod epub;
-mod kakuyomu;
-mod novel_utils;
-mod syosetu;
+mod aozora;
+pub mod epub;
+pub mod kakuyomu;
+mod local_text;
+pub mod manifest;
+mod markdown;
+pub mod novel_utils;
+mod omnibus;
+pub mod post_process;
+pub mod syosetu;
+mod volume_records;
 
 use std::{
-    path::{Path},
+    fs, mem,
+    path::{Path, PathBuf},
 };
 use isahc::http::{Uri};
+use serde::{Deserialize, Serialize};
 
 use ebook_builder::{
-    Book, EBookType, FileType, ReadingDir,
+    EBookType, FileType, ReadingDir,
     xml_tree::xhtml_prelude::*,
 };
 
-use crate::{NovelResult};
+use crate::{
+    NovelComponent, NovelError, NovelInfo, NovelResult, OmnibusInfo, RunInfo,
+    novel::{
+        epub::{NovelBook},
+        manifest::{ChapterManifest},
+        post_process::{PostProcessPipeline},
+        volume_records::{VolumeRecords},
+    },
+    progress::{ProgressObserver},
+};
 
 #[derive(Debug)]
 pub struct Novel {
+    // Only the base text of any ruby, so that it works for file names
     title: String,
+    // The title with its ruby, when it has any. Overriding the title leaves this out
+    title_ruby: Option< Vec<Content> >,
     author: String,
     status: NovelStatus,
+    // Like R15 and 残酷な描写あり. Not every site has them
+    ratings: Vec<String>,
+    keywords: Vec<String>,
     source_url: String,
+    // Goes after the number of each chapter, since every site counts them differently
+    chapter_counter: String,
+    reading_dir: ReadingDirection,
     // Since there may not be any sections
     contents: NovelContents,
 }
 impl Novel {
     pub fn print_name(&self) -> String { format!("{} [{}]", &self.title, &self.author) }
-    pub fn save_epubs(&self, save_dir: impl AsRef<Path>) -> NovelResult<()> {
-        let save_dir = save_dir.as_ref();
+    pub fn status(&self) -> NovelStatus { self.status }
+    pub fn chapter_count(&self) -> u32 {
+        match &self.contents {
+            NovelContents::Sections(sections) => sections.iter()
+                .map(|section| section.chapters.len() as u32)
+                .sum(),
+            NovelContents::Chapters(chapters) => chapters.len() as u32,
+        }
+    }
+    // Novels with only chapters don't have any sections at all
+    pub fn section_count(&self) -> usize {
         match &self.contents {
+            NovelContents::Sections(sections) => sections.len(),
+            NovelContents::Chapters(_) => 0,
+        }
+    }
+    pub fn post_process(&mut self, pipeline: &PostProcessPipeline) {
+        if pipeline.is_empty() {
+            return;
+        }
+        let chapters: Vec<&mut Chapter> = match &mut self.contents {
+            NovelContents::Sections(sections) => sections.iter_mut()
+                .flat_map(|section| section.chapters.iter_mut())
+                .collect(),
+            NovelContents::Chapters(chapters) => chapters.iter_mut().collect(),
+        };
+        for chapter in chapters {
+            chapter.content = pipeline.run(mem::take(&mut chapter.content));
+        }
+    }
+    // The sections that were asked for go first, then the rest in the same order as before
+    fn reorder_sections(&mut self, section_order: &[SectionKey]) {
+        let sections = match &mut self.contents {
+            NovelContents::Sections(sections) if !section_order.is_empty() => sections,
+            _ => return,
+        };
+        let mut remaining: Vec<(usize, Section)> = sections.drain(..).enumerate().collect();
+        let mut ordered = Vec::new();
+        for key in section_order.iter() {
+            match remaining.iter().position(|(i, section)| key.matches(*i, section)) {
+                Some(position) => ordered.push(remaining.remove(position).1),
+                None => println!("Couldn't find the section {:?} to reorder", key),
+            }
+        }
+        ordered.extend(remaining.into_iter().map(|(_, section)| section));
+        *sections = ordered;
+    }
+    // Takes the chapters that aren't a part of the story (like 設定資料) out of where they were
+    //  and puts them together at the end. Sections left without any chapters go away
+    //  Without sections, they only get moved to the end since there isn't a section to put them in
+    fn move_appendix_chapters(&mut self, appendix_patterns: &[String]) {
+        if appendix_patterns.is_empty() {
+            return;
+        }
+        let is_appendix = |chapter: &Chapter| appendix_patterns.iter().any(|pattern| {
+            chapter.name.contains(pattern.as_str()) || chapter.tags.contains(pattern)
+        });
+        match &mut self.contents {
             NovelContents::Sections(sections) => {
-                let books = self.make_section_epubs(&sections)?;
-                for (book, book_name) in books {
-                    let book_name = crate::sanitize_book_name(&book_name);
-                    let book_path = save_dir.join(format!("{}.epub", book_name));
-                    book.save_to_file(EBookType::Epub, book_path, true)?;
+                let mut appendix_chapters = Vec::new();
+                for section in sections.iter_mut() {
+                    let (appendix, story): (Vec<Chapter>, Vec<Chapter>) =
+                        mem::replace(&mut section.chapters, Vec::new()).into_iter()
+                            .partition(|chapter| is_appendix(chapter));
+                    appendix_chapters.extend(appendix);
+                    section.chapters = story;
                 }
+                if appendix_chapters.is_empty() {
+                    return;
+                }
+                println!("Moving {} chapters into the appendix", appendix_chapters.len());
+                sections.retain(|section| !section.chapters.is_empty());
+                sections.push(Section {
+                    name: APPENDIX_SECTION_NAME.to_string(),
+                    description: None,
+                    chapters: appendix_chapters,
+                });
             },
             NovelContents::Chapters(chapters) => {
-                let (book, book_name) = self.make_chapter_epub(&chapters)?;
-                let book_name = crate::sanitize_book_name(&book_name);
-                let book_path = save_dir.join(format!("{}.epub", book_name));
-                book.save_to_file(EBookType::Epub, book_path, true)?;
+                let (appendix, story): (Vec<Chapter>, Vec<Chapter>) =
+                    mem::replace(chapters, Vec::new()).into_iter()
+                        .partition(|chapter| is_appendix(chapter));
+                *chapters = story;
+                chapters.extend(appendix);
             },
         }
+    }
+    fn all_chapters(&self) -> Vec<&Chapter> {
+        match &self.contents {
+            NovelContents::Sections(sections) => sections.iter()
+                .flat_map(|section| section.chapters.iter())
+                .collect(),
+            NovelContents::Chapters(chapters) => chapters.iter().collect(),
+        }
+    }
+    // The prefix keeps the files apart from the other novels when they share a book
+    fn file_names(&self, prefix: &str) -> FileNames {
+        let max_order_num = self.all_chapters().iter()
+            .map(|chapter| chapter.order_num)
+            .max()
+            .unwrap_or(0);
+        FileNames {
+            prefix: prefix.to_string(),
+            chapter_digits: max_order_num.to_string().len(),
+        }
+    }
+    pub fn save_epubs(&self, run_info: &RunInfo) -> NovelResult<()> {
+        self.validate_output(run_info)?;
+        let book_dir = self.book_dir(run_info)?;
+        let file_names = self.file_names("");
+        let built_books = match &self.contents {
+            _ if run_info.one_epub_per_chapter =>
+                self.make_per_chapter_epubs(run_info, &file_names)?,
+            NovelContents::Sections(sections) =>
+                self.make_section_epubs(&sections, run_info, &file_names)?,
+            NovelContents::Chapters(chapters) =>
+                vec![self.make_chapter_epub(&chapters, run_info, &file_names)?],
+        };
+        for BuiltBook { mut book, name, chapters, volume_num } in built_books {
+            if run_info.chapter_toc_link {
+                add_contents_page(&mut book, chapters.iter().map(|chapter|
+                    (file_names.chapter(chapter.order_num), chapter.name.as_str())))?;
+            }
+            if run_info.include_colophon {
+                self.add_colophon(&mut book)?;
+            }
+            let book_name = crate::truncate_book_name(&crate::sanitize_book_name(&name),
+                crate::MAX_BOOK_NAME_BYTES);
+            let book_path = book_dir.join(
+                format!("{}.{}", book_name, run_info.ebook_type.extension()));
+            book.save_to_file(run_info.ebook_type.ebook_type(), &book_path)?;
+            print_book_size(&book_name, &book_path);
+            if run_info.verify_links {
+                print_broken_links(&book_name, &book_path);
+            }
+            if run_info.write_manifest {
+                let manifest_path = book_dir.join(
+                    format!("{}.{}", book_name, manifest::MANIFEST_EXTENSION));
+                manifest::write_manifest(self, &chapters, volume_num, &manifest_path)?;
+            }
+            if run_info.write_chapter_index {
+                let index_path = book_dir.join(
+                    format!("{}.{}", book_name, manifest::CHAPTER_INDEX_EXTENSION));
+                manifest::write_chapter_index(self, &chapters, &file_names, &index_path)?;
+            }
+            if run_info.export_markdown {
+                markdown::write_markdown(&chapters, &book_dir.join(&book_name))?;
+            }
+            if run_info.export_aozora {
+                aozora::write_aozora(self, &chapters,
+                    &book_dir.join(format!("{}.txt", book_name)))?;
+            }
+            if let Some(command) = run_info.post_build_command.as_ref() {
+                crate::run_post_build_command(command, &book_path);
+            }
+        }
         Ok(())
     }
 
-    fn start_book(&self) -> NovelResult<Book> {
-        let mut book = Book::new(&self.title, ReadingDir::Rtl, "ja");
-        book.add_author(&self.author, None);
-        let title_page: Vec<u8> = epub::start_xhtml("表紙", BodyTag::new()
-                .append_child(H1Tag::new().text(&self.title))
-                .append_child(H2Tag::new().text(&self.author))
-                .append_child(H3Tag::new()
+    // Renders every chapter ahead of time, to catch the ones that something emptied out
+    //  (like a post-processor or a selector that matches the wrong element)
+    fn validate_output(&self, run_info: &RunInfo) -> NovelResult<()> {
+        if let OutputValidation::Off = run_info.validate_output {
+            return Ok(());
+        }
+        let mut empty_order_nums = Vec::new();
+        for chapter in self.all_chapters() {
+            let page = chapter.make_xhtml(run_info, &self.chapter_counter);
+            if !epub::has_text(page, CHAPTER_CONTENTS_SELECTOR)? {
+                println!("Warning: Chapter {} ({}) of {} came out empty", chapter.order_num,
+                    &chapter.name, self.print_name());
+                empty_order_nums.push(chapter.order_num);
+            }
+        }
+        match run_info.validate_output {
+            OutputValidation::Fail if !empty_order_nums.is_empty() =>
+                Err(NovelError::EmptyChapters { order_nums: empty_order_nums }),
+            _ => Ok(()),
+        }
+    }
+
+    fn start_book(&self, run_info: &RunInfo, file_names: &FileNames) -> NovelResult<NovelBook> {
+        let mut book = NovelBook::new(&self.title, self.reading_dir, run_info.xhtml_format);
+        book.add_author(&self.author);
+        if !run_info.skip_title_page {
+            self.add_title_page(&mut book, run_info, file_names, "表紙")?;
+        }
+        add_styles(&mut book, run_info, self.reading_dir)?;
+        Ok(book)
+    }
+    fn add_title_page(&self, book: &mut NovelBook, run_info: &RunInfo, file_names: &FileNames,
+    nav_name: &str) -> NovelResult<()> {
+        let title_page_body = run_info.title_page.elements.iter()
+            .fold(BodyTag::new(), |body, element| match element {
+                TitlePageElement::Title => body.append_child(match self.title_ruby.as_ref() {
+                    Some(title_ruby) => title_ruby.iter()
+                        .fold(H1Tag::new(), |h1_tag, content| match content {
+                            Content::Span(text) => h1_tag.text(&epub::xml_text(text)),
+                            Content::Ruby { main, above } =>
+                                h1_tag.append_child(Content::ruby_tag(main, above)),
+                        }),
+                    None => H1Tag::new().text(&epub::xml_text(&self.title)),
+                }),
+                TitlePageElement::Author => body.append_child(H2Tag::new()
+                    .text(&epub::xml_text(&self.author))
+                ),
+                TitlePageElement::Status => body.append_child(H3Tag::new()
                     .text("投稿版　")
                     .text(self.status.status_text())
-                )
-                .append_child(ATag::new()
+                ),
+                TitlePageElement::SourceUrl => body.append_child(ATag::new()
                     .attr_href(&self.source_url)
                     // Display it as text in case the link doesn't work
-                    .text(&self.source_url)
-                )
-            ).write_doc_to(Vec::new())?;
-        book.add_file_as_bytes("title-cover.xhtml", &title_page, FileType::Xhtml);
-        book.mark_as_chapter_start("表紙");
-
-        book.add_file_as_bytes(epub::NOVEL_CSS_NAME, epub::NOVEL_CSS.as_bytes(), FileType::Css);
+                    .text(&epub::xml_text(&self.source_url))
+                ),
+                TitlePageElement::Keywords
+                if self.ratings.is_empty() && self.keywords.is_empty() => body,
+                TitlePageElement::Keywords => body.append_child(PTag::new()
+                    .attr_class("novel-keywords")
+                    .text(&epub::xml_text(&self.ratings.iter().chain(self.keywords.iter())
+                        .map(|keyword| keyword.as_str())
+                        .collect::< Vec<_> >()
+                        .join("・")))
+                ),
+            });
+        book.add_page(&file_names.title_cover(),
+            epub::start_xhtml("表紙", title_page_body), nav_name)
+    }
 
-        Ok(book)
+    // This has to go in last so that it ends up after all of the chapters
+    fn add_colophon(&self, book: &mut NovelBook) -> NovelResult<()> {
+        let colophon = epub::start_xhtml("奥付", BodyTag::new()
+                .append_child(H1Tag::new().text(&epub::xml_text(&self.title)))
+                .append_child(H2Tag::new().text(&epub::xml_text(&self.author)))
+                .append_child(PTag::new()
+                    .text(&format!("全{}部分　", self.chapter_count()))
+                    .text(self.status.status_text())
+                )
+                .append_child(PTag::new()
+                    .text("掲載元　")
+                    .append_child(ATag::new()
+                        .attr_href(&self.source_url)
+                        .text(&epub::xml_text(&self.source_url))
+                    )
+                )
+                .append_child(PTag::new()
+                    .text("取得日　")
+                    .text(&novel_utils::today_ja())
+                )
+                .append_child(PTag::new()
+                    .text(&format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")))
+                )
+            );
+        book.add_page("colophon.xhtml", colophon, "奥付")
     }
 
-    fn make_section_epubs(&self, sections: &[Section]) -> NovelResult< Vec<(Book, String)> > {
-        let base_book = self.start_book()?;
+    fn make_section_epubs<'a>(&self, sections: &'a [Section], run_info: &RunInfo,
+    file_names: &FileNames) -> NovelResult< Vec<BuiltBook<'a>> > {
+        let base_book = self.start_book(run_info, file_names)?;
         let mut books = Vec::new();
 
-        let total_sections = sections.len();
-        for (i, section) in sections.iter().enumerate() {
-            let book = section.fill_out_book(i + 1, base_book.clone())?;
-            let book_name = self.section_book_name(section, i, total_sections);
-            books.push( (book, book_name) );
+        let volumes = if run_info.stable_volume_numbers {
+            let mut volume_records = VolumeRecords::load(&run_info.save_dir);
+            let volumes = volume_records.group_sections(&self.source_url,
+                run_info.volume_strategy, sections);
+            volume_records.save(&run_info.save_dir)?;
+            volumes
+        } else {
+            run_info.volume_strategy.group_sections(sections)
+        };
+        let total_volumes = volumes.len();
+        for (i, volume) in volumes.iter().enumerate() {
+            let mut book = base_book.clone();
+            for (i, (section_index, section)) in volume.iter().enumerate() {
+                if i > 0 {
+                    add_separator(&mut book, run_info, &file_names.separator(section_index + 1))?;
+                }
+                book = section.fill_out_book(section_index + 1, book, run_info,
+                    &self.chapter_counter, file_names)?;
+            }
+            books.push(BuiltBook {
+                book,
+                name: self.volume_book_name(volume, i, total_volumes, run_info),
+                chapters: volume.iter()
+                    .flat_map(|(_, section)| section.chapters.iter())
+                    .collect(),
+                volume_num: Some(i + 1),
+            });
         }
         Ok(books)
     }
-    fn make_chapter_epub(&self, chapters: &[Chapter]) -> NovelResult<(Book, String)> {
-        let mut book = self.start_book()?;
+    fn make_chapter_epub<'a>(&self, chapters: &'a [Chapter], run_info: &RunInfo,
+    file_names: &FileNames) -> NovelResult< BuiltBook<'a> > {
+        let mut book = self.start_book(run_info, file_names)?;
         for chapter in chapters.iter() {
-            chapter.add_to_book(&mut book)?;
+            chapter.add_to_book(&mut book, run_info, &self.chapter_counter, file_names)?;
+        }
+        Ok(BuiltBook {
+            book,
+            name: self.chapters_book_name(chapters, run_info),
+            chapters: chapters.iter().collect(),
+            volume_num: None,
+        })
+    }
+
+    // Sections don't matter here since every chapter is on its own anyways
+    fn make_per_chapter_epubs(&self, run_info: &RunInfo, file_names: &FileNames)
+    -> NovelResult< Vec<BuiltBook> > {
+        let base_book = self.start_book(run_info, file_names)?;
+        let chapters = self.all_chapters();
+
+        let mut books = Vec::new();
+        for chapter in chapters {
+            let mut book = base_book.clone();
+            chapter.add_to_book(&mut book, run_info, &self.chapter_counter, file_names)?;
+            // Padding the number keeps the files sorted in the right order
+            let name = format!("{} {:0width$}部分 「{}」 [{}] (投稿版)",
+                &self.title, chapter.order_num, &chapter.name, &self.author,
+                width = file_names.chapter_digits);
+            books.push(BuiltBook {
+                book,
+                name,
+                chapters: vec![chapter],
+                volume_num: None,
+            });
+        }
+        Ok(books)
+    }
+
+    // Only the title page and a list of every chapter (linking to the site), to decide
+    //  whether the whole novel is worth making
+    pub fn save_stub_epub(&self, chapters: &[ChapterManifest], run_info: &RunInfo)
+    -> NovelResult<()> {
+        let file_names = self.file_names("");
+        let mut book = self.start_book(run_info, &file_names)?;
+        let chapter_list_body = chapters.iter()
+            .fold(BodyTag::new().append_child(H1Tag::new().text("目次")), |body, chapter| {
+                body.append_child(PTag::new()
+                    .text(&epub::xml_text(
+                        &format!("{}{}　", chapter.order_num, &self.chapter_counter)))
+                    .append_child(ATag::new()
+                        .attr_href(&chapter.source_url)
+                        .text(&epub::xml_text(&chapter.name))
+                    )
+                    .text(&epub::xml_text(&format!("　{}", &chapter.date)))
+                )
+            });
+        book.add_page(CONTENTS_FILE_NAME, epub::start_xhtml("目次", chapter_list_body), "目次")?;
+
+        let name = format!("{} [{}] (目次のみ) (全{}部分){}", &self.title, &self.author,
+            chapters.len(), self.status.kan_stamp(run_info));
+        let book_name = crate::truncate_book_name(&crate::sanitize_book_name(&name),
+            crate::MAX_BOOK_NAME_BYTES);
+        let book_path = self.book_dir(run_info)?.join(
+            format!("{}.{}", book_name, run_info.ebook_type.extension()));
+        book.save_to_file(run_info.ebook_type.ebook_type(), &book_path)?;
+        print_book_size(&book_name, &book_path);
+        Ok(())
+    }
+    // Makes the folder for the novel's status when it isn't there yet
+    fn book_dir(&self, run_info: &RunInfo) -> NovelResult<PathBuf> {
+        if !run_info.group_by_status {
+            return Ok(run_info.save_dir.clone());
         }
-        Ok( (book, self.chapters_book_name(chapters)) )
+        let book_dir = run_info.save_dir.join(self.status.status_text());
+        fs::create_dir_all(&book_dir)?;
+        Ok(book_dir)
     }
 
-    fn section_book_name(&self, section: &Section, section_index: usize, total_sections: usize)
-    -> String {
-        let max_sections_num_digits = total_sections.to_string().len();
-        // The section number will need to be left padded with 0s
+    fn volume_book_name(&self, volume: &[(usize, &Section)], volume_index: usize,
+    total_volumes: usize, run_info: &RunInfo) -> String {
+        let max_volumes_num_digits = total_volumes.to_string().len();
+        // The volume number will need to be left padded with 0s
         //  So that each number will have to same number of digits
-        let section_num = {
+        let volume_num = {
             // Starting at 0 for book numbers feels weird
-            let simple_section_num = (section_index + 1).to_string();
-            let diff = max_sections_num_digits - simple_section_num.len();
+            let simple_volume_num = (volume_index + 1).to_string();
+            let diff = max_volumes_num_digits - simple_volume_num.len();
             if diff > 0 {
-                "0".repeat(diff) + &simple_section_num
+                "0".repeat(diff) + &simple_volume_num
             } else {
-                simple_section_num
+                simple_volume_num
             }
         };
-        let chapter_range = chapter_range(&section.chapters);
-        let kan_stamp = if section_index == total_sections - 1 {
-            self.status.kan_stamp()
+        let first_section = volume.first().unwrap().1;
+        let last_section = volume.last().unwrap().1;
+        let volume_name = if volume.len() == 1 {
+            first_section.name.clone()
+        } else {
+            format!("{}～{}", &first_section.name, &last_section.name)
+        };
+        let chapter_range = (
+            chapter_range(&first_section.chapters).0,
+            chapter_range(&last_section.chapters).1,
+        );
+        let kan_stamp = if volume_index == total_volumes - 1 {
+            self.status.kan_stamp(run_info)
         } else { "" };
-        format!("{} {} 「{}」 [{}] (投稿版) ({}部分-{}部分){}",
-            &self.title, section_num, &section.name, &self.author,
-            chapter_range.0, chapter_range.1, kan_stamp)
+        let section_chapter_count: usize = volume.iter()
+            .map(|(_, section)| section.chapters.len())
+            .sum();
+        run_info.volume_name_format
+            .replace("{title}", &self.title)
+            .replace("{volume_num}", &volume_num)
+            .replace("{volume_name}", &volume_name)
+            .replace("{author}", &self.author)
+            .replace("{first_chapter}", &chapter_range.0.to_string())
+            .replace("{last_chapter}", &chapter_range.1.to_string())
+            .replace("{section_chapter_count}", &section_chapter_count.to_string())
+            .replace("{kan_stamp}", kan_stamp)
     }
-    fn chapters_book_name(&self, chapters: &[Chapter]) -> String {
+    fn chapters_book_name(&self, chapters: &[Chapter], run_info: &RunInfo) -> String {
         let chapter_range = chapter_range(chapters);
         format!("{} [{}] (投稿版) ({}部分-{}部分){}",
             &self.title, &self.author,
-            chapter_range.0, chapter_range.1, self.status.kan_stamp())
+            chapter_range.0, chapter_range.1, self.status.kan_stamp(run_info))
+    }
+}
+
+// A book that's much smaller than the others usually means that something didn't get parsed
+fn print_book_size(book_name: &str, book_path: &Path) {
+    match fs::metadata(book_path) {
+        Ok(metadata) => println!("Saved {} ({} KB)", book_name, metadata.len() / 1024),
+        Err(e) => println!("Couldn't check the size of {}: {:?}", book_name, e),
+    }
+}
+// Reads the book back, so that the links get checked where the files really ended up
+fn print_broken_links(book_name: &str, book_path: &Path) {
+    match epub::broken_links(book_path) {
+        Ok(broken_links) => for broken_link in broken_links {
+            println!("Broken link in {}: {}", book_name, broken_link);
+        },
+        Err(e) => println!("Couldn't check the links in {}: {:?}", book_name, e),
+    }
+}
+
+// Every book only needs these once, no matter how many novels are in it
+fn add_styles(book: &mut NovelBook, run_info: &RunInfo, reading_dir: ReadingDirection)
+-> NovelResult<()> {
+    let font_file_name = match run_info.font_path.as_ref() {
+        Some(font_path) => {
+            let font_file_name = font_path.file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| "font".to_string());
+            let font = fs::read(font_path)?;
+            println!("Embedding {} makes every book {} KB bigger", &font_file_name,
+                font.len() / 1024);
+            book.add_resource(&font_file_name, &font, FileType::Font);
+            Some(font_file_name)
+        },
+        None => None,
+    };
+    let novel_css = epub::novel_css(font_file_name.as_ref().map(|name| name.as_str()),
+        reading_dir);
+    book.add_resource(epub::NOVEL_CSS_NAME, novel_css.as_bytes(), FileType::Css);
+    Ok(())
+}
+
+// Everything about a novel we can find without fetching any of the chapters
+#[derive(Debug)]
+pub struct NovelOverview {
+    pub title: String,
+    pub title_ruby: Option< Vec<Content> >,
+    pub author: String,
+    pub status: NovelStatus,
+    // The novel's introduction (like あらすじ), when the site has one
+    pub synopsis: Option<String>,
+    pub ratings: Vec<String>,
+    pub keywords: Vec<String>,
+    pub chapter_count: u32,
+}
+impl NovelOverview {
+    pub fn print_name(&self) -> String { format!("{} [{}]", &self.title, &self.author) }
+}
+
+// Enough to tell whether a novel has been updated, for something like a reading list
+#[derive(Debug, Serialize)]
+pub struct NovelMetadata {
+    pub title: String,
+    pub author: String,
+    pub status: NovelStatus,
+    pub synopsis: Option<String>,
+    pub chapter_count: u32,
+    // The date of the chapter with the highest number, as the site writes it
+    pub latest_chapter_date: Option<String>,
+}
+impl NovelMetadata {
+    fn new(overview: NovelOverview, chapters: &[ChapterManifest]) -> NovelMetadata {
+        NovelMetadata {
+            title: overview.title,
+            author: overview.author,
+            status: overview.status,
+            synopsis: overview.synopsis,
+            chapter_count: overview.chapter_count,
+            latest_chapter_date: chapters.iter()
+                .max_by_key(|chapter| chapter.order_num)
+                .map(|chapter| chapter.date.clone()),
+        }
     }
 }
+// Local novels don't have any chapter dates to go by
+pub fn fetch_local_metadata(local_dir: &Path, novel_info: &NovelInfo)
+-> NovelResult<NovelMetadata> {
+    Ok(NovelMetadata::new(fetch_local_overview(local_dir, novel_info)?, &[]))
+}
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
 pub enum NovelStatus {
     Running,
     Finished,
 }
 impl NovelStatus {
-    fn kan_stamp(self) -> &'static str {
+    // Leaving it out keeps the book's name the same after the novel finishes
+    fn kan_stamp(self, run_info: &RunInfo) -> &'static str {
         match self {
+            _ if !run_info.include_kan_stamp => "",
             Self::Running => "",
             // The space needs to be here for easy formatting
             Self::Finished => " (完)",
         }
     }
-    fn status_text(self) -> &'static str {
+    pub fn status_text(self) -> &'static str {
         match self {
             Self::Running => "連載中",
             Self::Finished => "完結済",
         }
     }
 }
+// Every folder that a novel's books (and manifests) can be saved in
+pub fn book_dirs(run_info: &RunInfo) -> Vec<PathBuf> {
+    let mut book_dirs = vec![run_info.save_dir.clone()];
+    if run_info.group_by_status {
+        book_dirs.extend([NovelStatus::Running, NovelStatus::Finished].iter()
+            .map(|status| run_info.save_dir.join(status.status_text())));
+    }
+    book_dirs
+}
 
+// The name of every page that a novel puts into a book
+#[derive(Debug)]
+struct FileNames {
+    // Keeps them apart from the other novels in the same book
+    prefix: String,
+    // Chapter numbers get padded to this many digits, so that the files sort in order
+    chapter_digits: usize,
+}
+impl FileNames {
+    fn title_cover(&self) -> String { format!("{}title-cover.xhtml", &self.prefix) }
+    fn section_cover(&self, section_num: usize) -> String {
+        format!("{}section-cover-{}.xhtml", &self.prefix, section_num)
+    }
+    // Goes before the section with this number. 0 is before the whole novel
+    fn separator(&self, section_num: usize) -> String {
+        format!("{}separator-{}.xhtml", &self.prefix, section_num)
+    }
+    fn chapter(&self, order_num: u32) -> String {
+        format!("{}chapter-{:0width$}.xhtml", &self.prefix, order_num, width = self.chapter_digits)
+    }
+}
+
+// A book that's ready to be saved, along with the chapters that went into it
+struct BuiltBook<'a> {
+    book: NovelBook,
+    name: String,
+    chapters: Vec<&'a Chapter>,
+    // Only when the sections were split up into volumes
+    volume_num: Option<usize>,
+}
+
+// Japanese novels are vertical and go from right to left, but a translated one might not
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadingDirection {
+    Rtl,
+    // Also makes the text horizontal
+    Ltr,
+}
+impl Default for ReadingDirection {
+    fn default() -> Self { Self::Rtl }
+}
+impl ReadingDirection {
+    fn reading_dir(self) -> ReadingDir {
+        match self {
+            Self::Rtl => ReadingDir::Rtl,
+            Self::Ltr => ReadingDir::Ltr,
+        }
+    }
+}
+
+// ebook_builder can only make reflowable epubs for now
+//  Fixed layout (or any other kind) can go here once it's supported there
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookType {
+    Epub,
+}
+impl Default for BookType {
+    fn default() -> Self { Self::Epub }
+}
+impl BookType {
+    fn ebook_type(self) -> EBookType {
+        match self {
+            Self::Epub => EBookType::Epub,
+        }
+    }
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Epub => "epub",
+        }
+    }
+}
+
+// Leaving an element out of the list leaves it off of the title page completely
+#[derive(Debug, Deserialize)]
+pub struct TitlePageOptions {
+    elements: Vec<TitlePageElement>,
+}
+impl Default for TitlePageOptions {
+    fn default() -> Self {
+        Self {
+            elements: vec![
+                TitlePageElement::Title,
+                TitlePageElement::Author,
+                TitlePageElement::Status,
+                TitlePageElement::SourceUrl,
+                TitlePageElement::Keywords,
+            ],
+        }
+    }
+}
+// Each one always looks the same, no matter where it is on the page
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TitlePageElement {
+    Title,
+    Author,
+    Status,
+    SourceUrl,
+    // Left out when the site doesn't have any
+    Keywords,
+}
+
+// Decides which sections will end up together in the same book
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VolumeStrategy {
+    // Every section gets its own book
+    PerSection,
+    // Sections are added to a book until it has at least this many chapters
+    ChapterCount(usize),
+}
+impl Default for VolumeStrategy {
+    fn default() -> Self { Self::PerSection }
+}
+impl VolumeStrategy {
+    fn group_sections(self, sections: &[Section]) -> Vec< Vec<(usize, &Section)> > {
+        let mut volumes = Vec::new();
+        match self {
+            Self::PerSection => {
+                for (i, section) in sections.iter().enumerate() {
+                    volumes.push(vec![(i, section)]);
+                }
+            },
+            Self::ChapterCount(target_count) => {
+                let mut volume = Vec::new();
+                let mut volume_chapter_count = 0;
+                for (i, section) in sections.iter().enumerate() {
+                    volume.push((i, section));
+                    volume_chapter_count += section.chapters.len();
+                    if volume_chapter_count >= target_count {
+                        volumes.push(volume);
+                        volume = Vec::new();
+                        volume_chapter_count = 0;
+                    }
+                }
+                // The last volume can come up short
+                if !volume.is_empty() {
+                    volumes.push(volume);
+                }
+            },
+        }
+        volumes
+    }
+}
+
+// Lets a single novel use its own CSS selectors instead of the ones from its site
+#[derive(Debug, Default, Deserialize)]
+pub struct SelectorOverrides {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub section: Option<String>,
+    pub section_description: Option<String>,
+    pub chapter: Option<String>,
+    // The element that directly holds all of the lines of a chapter
+    pub content: Option<String>,
+    // Tried in order after the content selector, for when the site serves more than one layout
+    #[serde(default)]
+    pub content_fallbacks: Vec<String>,
+}
+impl SelectorOverrides {
+    fn title<'a>(&'a self, default: &'a str) -> &'a str { override_or(&self.title, default) }
+    fn author<'a>(&'a self, default: &'a str) -> &'a str { override_or(&self.author, default) }
+    fn section<'a>(&'a self, default: &'a str) -> &'a str { override_or(&self.section, default) }
+    fn section_description<'a>(&'a self, default: &'a str) -> &'a str {
+        override_or(&self.section_description, default)
+    }
+    fn chapter<'a>(&'a self, default: &'a str) -> &'a str { override_or(&self.chapter, default) }
+    // Every selector to try for the contents, in order. The first that finds any lines wins
+    fn content(&self, defaults: &[&str]) -> Vec<String> {
+        let main_selectors = match self.content.as_ref() {
+            Some(content) => vec![content.clone()],
+            None => defaults.iter().map(|default| default.to_string()).collect(),
+        };
+        main_selectors.into_iter().chain(self.content_fallbacks.iter().cloned()).collect()
+    }
+}
+fn override_or<'a>(selector: &'a Option<String>, default: &'a str) -> &'a str {
+    match selector {
+        Some(selector) => selector.as_str(),
+        None => default,
+    }
+}
+
+// Puts every one of the novels into the same book, one after another
+pub fn save_omnibus(omnibus_info: &OmnibusInfo, novels: &[Novel], run_info: &RunInfo)
+-> NovelResult<()> {
+    self::omnibus::save_omnibus(omnibus_info, novels, run_info)
+}
+
+// Gives back the URL of every novel in a list of them, like a Kakuyomu collection
+pub fn fetch_collection(collection_url: &str) -> NovelResult< Vec<String> > {
+    let uri: Uri = collection_url.parse()?;
+    if self::kakuyomu::is_kakuyomu_collection(&uri) {
+        self::kakuyomu::fetch_kakuyomu_collection(&uri)
+    } else {
+        Err(NovelError::NotANovel)
+    }
+}
+
+// For novels that only exist as text files, which don't come from any site
+pub fn make_local_novel(local_dir: &Path, novel_info: &NovelInfo) -> NovelResult<Novel> {
+    self::local_text::make_local_novel(local_dir, novel_info)
+}
+pub fn fetch_local_overview(local_dir: &Path, novel_info: &NovelInfo)
+-> NovelResult<NovelOverview> {
+    let novel = self::local_text::make_local_novel(local_dir, novel_info)?;
+    Ok(NovelOverview {
+        chapter_count: novel.chapter_count(),
+        title: novel.title,
+        title_ruby: novel.title_ruby,
+        author: novel.author,
+        status: novel.status,
+        synopsis: None,
+        ratings: novel.ratings,
+        keywords: novel.keywords,
+    })
+}
+
+const APPENDIX_SECTION_NAME: &'static str = "付録";
 #[derive(Debug)]
 enum NovelContents {
     Sections(Vec<Section>),
     Chapters(Vec<Chapter>),
 }
+// Every site finds sections (or only chapters) on its main page, which then get fetched
+//  Keeping it in one place makes sure that every site treats them the same way
+//  A section without any chapters is a mistake, unless the novel says to leave them out
+fn assemble_contents<S, C>(mut section_infos: Vec<S>, chapter_infos: Vec<C>,
+    drop_empty_sections: bool,
+    section_is_empty: impl Fn(&S) -> bool,
+    fetch_sections: impl FnOnce(Vec<S>) -> NovelResult< Vec<Section> >,
+    fetch_chapters: impl FnOnce(Vec<C>) -> NovelResult< Vec<Chapter> >,
+) -> NovelResult<NovelContents> {
+    if drop_empty_sections {
+        let section_count = section_infos.len();
+        section_infos.retain(|section| !section_is_empty(section));
+        let dropped_count = section_count - section_infos.len();
+        if dropped_count > 0 {
+            println!("Warning: Leaving out {} sections that don't have any chapters yet",
+                dropped_count);
+        }
+    }
+    if section_infos.is_empty() {
+        if chapter_infos.is_empty() {
+            return Err(NovelError::ComponentMissing(NovelComponent::Chapter));
+        }
+        let mut chapters = fetch_chapters(chapter_infos)?;
+        sort_chapters(&mut chapters);
+        Ok(NovelContents::Chapters(chapters))
+    } else {
+        if section_infos.iter().any(section_is_empty) {
+            return Err(NovelError::ComponentMissing(NovelComponent::ChapterUnderSection));
+        }
+        let mut sections = fetch_sections(section_infos)?;
+        for section in sections.iter_mut() {
+            sort_chapters(&mut section.chapters);
+        }
+        Ok(NovelContents::Sections(sections))
+    }
+}
+// The chapters get fetched in parallel, so nothing else should count on them finishing in order
+//  The sort is stable, so chapters that somehow share a number keep the order they came in
+fn sort_chapters(chapters: &mut [Chapter]) {
+    chapters.sort_by_key(|chapter| chapter.order_num);
+}
+
+// Only the last chapters in the table of contents get kept, leaving out any sections that end up
+//  empty. It happens before anything gets fetched, so the rest don't cost anything
+fn keep_latest_chapters<S, C>(sections: &mut Vec<S>, chapters: &mut Vec<C>, latest: usize,
+    section_chapters: fn(&mut S) -> &mut Vec<C>) {
+    let mut remaining = latest;
+    let mut keep_last = |chapters: &mut Vec<C>| {
+        let kept = chapters.len().min(remaining);
+        chapters.drain(..chapters.len() - kept);
+        remaining -= kept;
+    };
+    keep_last(chapters);
+    for section in sections.iter_mut().rev() {
+        keep_last(section_chapters(section));
+    }
+    sections.retain_mut(|section| !section_chapters(section).is_empty());
+}
+
+// A section's place in the table of contents (starting at 1), or its name
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SectionKey {
+    Position(usize),
+    Name(String),
+}
+impl SectionKey {
+    fn matches(&self, index: usize, section: &Section) -> bool {
+        match self {
+            Self::Position(position) => *position == index + 1,
+            Self::Name(name) => name == &section.name,
+        }
+    }
+}
 
 #[derive(Debug)]
 struct Section {
     name: String,
+    // A short blurb about the section, which most novels don't have
+    description: Option<String>,
     chapters: Vec<Chapter>,
 }
 impl Section {
-    fn fill_out_book(&self, section_num: usize, mut book: Book) -> NovelResult<Book> {
+    fn fill_out_book(&self, section_num: usize, mut book: NovelBook, run_info: &RunInfo,
+    chapter_counter: &str, file_names: &FileNames) -> NovelResult<NovelBook> {
         // Make a new page that will just have the name of the section
         //  This will probably be just after the main page
-        let section_cover: Vec<u8> = epub::start_xhtml("章の表紙", BodyTag::new()
-                .append_child(H1Tag::new()
-                    .text(&section_num.to_string())
-                    .attr_class("center")
-                )
-                .append_child(H1Tag::new().text(&self.name))
+        let mut section_cover_body = BodyTag::new()
+            .append_child(H1Tag::new()
+                .text(&epub::xml_text(&run_info.section_label.replace("{num}",
+                    &novel_utils::convert_num_to_ja(section_num as u32))))
+                .attr_class("center section-start")
             )
-            .write_doc_to(Vec::new())?;
-        book.add_file_as_bytes("section-cover.xhtml", &section_cover, FileType::Xhtml);
-        book.mark_as_chapter_start("章の表紙");
+            .append_child(H1Tag::new().text(&epub::xml_text(&self.name)));
+        if let Some(description) = self.description.as_ref() {
+            section_cover_body = section_cover_body.append_child(PTag::new()
+                .attr_class("section-description")
+                .text(&epub::xml_text(description))
+            );
+        }
+        // Each section needs its own file since there can be more than one in a book
+        book.add_page(&file_names.section_cover(section_num),
+            epub::start_xhtml("章の表紙", section_cover_body), "章の表紙")?;
 
         for chapter in self.chapters.iter() {
-            chapter.add_to_book(&mut book)?;
+            chapter.add_to_book(&mut book, run_info, chapter_counter, file_names)?;
         }
 
         Ok(book)
@@ -182,51 +934,222 @@ struct Chapter {
     name: String,
     date: String,
     order_num: u32,
+    // Where the content of the chapter came from
+    source_url: String,
+    // Any labels the site puts next to the chapter name (like warnings). Usually empty
+    tags: Vec<String>,
     // The content MUST NOT have the name of the chapter
     //  We will insert it ourselves so that it will always show up exactly the way we want
     content: Vec<ContentLine>,
+    author_notes: AuthorNotes,
 }
 impl Chapter {
-    fn make_xhtml(&self) -> HtmlTag {
+    fn make_xhtml(&self, run_info: &RunInfo, chapter_counter: &str) -> HtmlTag {
         let content = self.content.iter()
-            .fold(DivTag::new().attr_id("novel_chapter_contents"),
-                |tag, content_line| tag.append_child(content_line.make_xhtml()));
+            .fold(DivTag::new().attr_id(CHAPTER_CONTENTS_ID),
+                |tag, content_line| content_line.append_to(tag));
         let part_num = novel_utils::convert_num_string_to_ja(&self.order_num.to_string());
 
-        epub::start_xhtml(&self.name, BodyTag::new()
+        let mut body = BodyTag::new()
             .attr_id("novel_chapter")
-            .append_child(H1Tag::new().text(&self.name))
-            .append_child(H2Tag::new().text(&self.date))
-            .append_child(H3Tag::new().text(&format!("{}部分目", part_num)))
-            .append_child(content)
-        )
-    }
-    fn add_to_book(&self, book: &mut Book) -> NovelResult<()> {
-        let chapter_page: Vec<u8> = self.make_xhtml()
-            .write_doc_to(Vec::new())?;
-        let chapter_file_name = format!("chapter-{}.xhtml", self.order_num);
-        book.add_file_as_bytes(&chapter_file_name, &chapter_page, FileType::Xhtml);
-        book.mark_as_chapter_start(&self.name);
-        Ok(())
+            .append_child(H1Tag::new()
+                .attr_class("chapter-start")
+                .text(&epub::xml_text(&self.name))
+            )
+            .append_child(H2Tag::new().text(&epub::xml_text(&self.date)))
+            .append_child(H3Tag::new()
+                .text(&epub::xml_text(&format!("{}{}", part_num, chapter_counter)))
+            );
+        if run_info.show_chapter_tags && !self.tags.is_empty() {
+            body = body.append_child(PTag::new()
+                .attr_class("chapter-tags")
+                .text(&epub::xml_text(&self.tags.join("・")))
+            );
+        }
+        let show_notes = run_info.include_author_notes;
+        if show_notes && !self.author_notes.before.is_empty() {
+            body = body.append_child(author_note_xhtml(&self.author_notes.before));
+        }
+        body = body.append_child(content);
+        if show_notes && !self.author_notes.after.is_empty() {
+            body = body.append_child(author_note_xhtml(&self.author_notes.after));
+        }
+        if run_info.chapter_toc_link {
+            body = body.append_child(PTag::new()
+                .attr_class("toc-link")
+                .append_child(ATag::new()
+                    .attr_href(CONTENTS_FILE_NAME)
+                    .text("目次へ")
+                )
+            );
+        }
+        epub::start_xhtml(&self.name, body)
+    }
+    fn add_to_book(&self, book: &mut NovelBook, run_info: &RunInfo, chapter_counter: &str,
+    file_names: &FileNames) -> NovelResult<()> {
+        // Only the navigation gets the date, since the page already shows it under the name
+        let nav_name = if run_info.nav_include_date && !self.date.is_empty() {
+            format!("{} — {}", &self.name, &self.date)
+        } else {
+            self.name.clone()
+        };
+        book.add_page(&file_names.chapter(self.order_num),
+            self.make_xhtml(run_info, chapter_counter), &nav_name)
     }
 }
 
-#[derive(Debug)]
+const CHAPTER_CONTENTS_ID: &'static str = "novel_chapter_contents";
+const CHAPTER_CONTENTS_SELECTOR: &'static str = "#novel_chapter_contents";
+
+// Checks every chapter's page before anything gets saved
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputValidation {
+    Off,
+    // Prints the chapters that came out empty, but still saves the books
+    Warn,
+    // Doesn't save any of the novel's books when a chapter came out empty
+    Fail,
+}
+impl Default for OutputValidation {
+    fn default() -> Self { Self::Off }
+}
+
+// What the author wrote before and after the story of a chapter (like 前書き and 後書き)
+//  Not every site keeps them apart from the story, so they're usually empty
+#[derive(Debug, Default)]
+struct AuthorNotes {
+    before: Vec<ContentLine>,
+    after: Vec<ContentLine>,
+}
+fn author_note_xhtml(lines: &[ContentLine]) -> DivTag {
+    lines.iter().fold(DivTag::new().attr_class("author-note"),
+        |tag, content_line| content_line.append_to(tag))
+}
+
+// Only a page with the symbol in the middle, to mark the break before the next section
+//  Nothing happens when there isn't a separator set
+fn add_separator(book: &mut NovelBook, run_info: &RunInfo, file_name: &str) -> NovelResult<()> {
+    if let Some(separator) = run_info.section_separator.as_ref() {
+        let separator_body = BodyTag::new()
+            .append_child(PTag::new()
+                .attr_class("separator")
+                .text(&epub::xml_text(separator))
+            );
+        book.add_unlisted_page(file_name, epub::start_xhtml("区切り", separator_body))?;
+    }
+    Ok(())
+}
+
+const CONTENTS_FILE_NAME: &'static str = "contents.xhtml";
+// Goes after the chapters since they all have to be known first
+//  Each link is the chapter's file name along with the chapter's name
+fn add_contents_page<'a>(book: &mut NovelBook, links: impl Iterator<Item = (String, &'a str)>)
+-> NovelResult<()> {
+    let contents_body = links
+        .fold(BodyTag::new().append_child(H1Tag::new().text("目次")), |body, (file_name, name)| {
+            body.append_child(PTag::new()
+                .append_child(ATag::new()
+                    .attr_href(&file_name)
+                    .text(&epub::xml_text(name))
+                )
+            )
+        });
+    book.add_page(CONTENTS_FILE_NAME, epub::start_xhtml("目次", contents_body), "目次")
+}
+
+#[derive(Debug, PartialEq)]
 pub enum ContentLine {
-    Line(Vec<Content>),
+    Line(Vec<Content>, LineAlign),
     Blank,
+    // Every item is a single line, like the stats on a status screen
+    List {
+        ordered: bool,
+        items: Vec< Vec<Content> >,
+    },
+    // Only simple grids, like a skill list. Anything more complex gets flattened into lines
+    Table(Vec<TableRow>),
 }
 impl ContentLine {
-    fn make_xhtml(&self) -> PTag {
+    fn char_count(&self) -> usize {
+        let contents_char_count = |contents: &Vec<Content>| -> usize {
+            contents.iter()
+                .map(|content| match content {
+                    Content::Span(text) => text.chars().count(),
+                    Content::Ruby { main, .. } => main.chars().count(),
+                })
+                .sum()
+        };
+        match self {
+            Self::Line(contents, _) => contents_char_count(contents),
+            Self::Blank => 0,
+            Self::List { items, .. } => items.iter().map(contents_char_count).sum(),
+            Self::Table(rows) => rows.iter()
+                .flat_map(|row| row.cells.iter())
+                .map(contents_char_count)
+                .sum(),
+        }
+    }
+    fn append_to(&self, tag: DivTag) -> DivTag {
+        let make_p = |contents: &Vec<Content>, p_tag: PTag| {
+            contents.iter().fold(p_tag, |p_tag, content| content.append_to(p_tag))
+        };
         match self {
-            Self::Line(contents) => contents.iter()
-                .fold(PTag::new(), |tag, content| content.append_to(tag)),
-            Self::Blank => PTag::new()
-                .append_child(BrTag::new()),
+            Self::Line(contents, align) => {
+                let p_tag = match align.css_class() {
+                    Some(class) => PTag::new().attr_class(class),
+                    None => PTag::new(),
+                };
+                tag.append_child(make_p(contents, p_tag))
+            },
+            Self::Blank => tag.append_child(PTag::new()
+                .append_child(BrTag::new())),
+            // Each item gets a <p> so that its text works the same as any other line
+            Self::List { ordered: true, items } => tag.append_child(items.iter()
+                .fold(OlTag::new(), |list, item| {
+                    list.append_child(LiTag::new().append_child(make_p(item, PTag::new())))
+                })),
+            Self::List { ordered: false, items } => tag.append_child(items.iter()
+                .fold(UlTag::new(), |list, item| {
+                    list.append_child(LiTag::new().append_child(make_p(item, PTag::new())))
+                })),
+            Self::Table(rows) => tag.append_child(rows.iter()
+                .fold(TableTag::new(), |table, row| {
+                    table.append_child(row.cells.iter().fold(TrTag::new(), |tr, cell| {
+                        let cell_p = make_p(cell, PTag::new());
+                        if row.is_header {
+                            tr.append_child(ThTag::new().append_child(cell_p))
+                        } else {
+                            tr.append_child(TdTag::new().append_child(cell_p))
+                        }
+                    }))
+                })),
         }
     }
 }
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
+pub struct TableRow {
+    // From <thead>, or a row that's nothing but <th>
+    is_header: bool,
+    cells: Vec< Vec<Content> >,
+}
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LineAlign {
+    // Whatever the reader would normally do
+    Start,
+    Center,
+    Right,
+}
+impl LineAlign {
+    fn css_class(self) -> Option<&'static str> {
+        match self {
+            Self::Start => None,
+            Self::Center => Some("align-center"),
+            Self::Right => Some("align-right"),
+        }
+    }
+}
+#[derive(Debug, PartialEq)]
 pub enum Content {
     Span(String),
     Ruby {
@@ -237,15 +1160,17 @@ pub enum Content {
 impl Content {
     fn append_to(&self, tag: PTag) -> PTag {
         match self {
-            Self::Span(text) => tag.text(&text),
-            Self::Ruby { main, above } => tag.append_child(RubyTag::new()
-                .text(&main)
-                .append_child(RpTag::new().text("（"))
-                .append_child(RtTag::new().text(&above))
-                .append_child(RpTag::new().text("）"))
-            ),
+            Self::Span(text) => tag.text(&epub::xml_text(text)),
+            Self::Ruby { main, above } => tag.append_child(Self::ruby_tag(main, above)),
         }
     }
+    fn ruby_tag(main: &str, above: &str) -> RubyTag {
+        RubyTag::new()
+            .text(&epub::xml_text(main))
+            .append_child(RpTag::new().text("（"))
+            .append_child(RtTag::new().text(&epub::xml_text(above)))
+            .append_child(RpTag::new().text("）"))
+    }
 }
 
 fn chapter_range(chapters: &[Chapter]) -> (u32, u32) {
@@ -270,11 +1195,192 @@ impl NovelSite {
         }
     }
 
-    // This should make as many other web requests as it needs
-    pub fn make_novel(&self, uri: Uri) -> NovelResult<Novel> {
+    // Everything the table of contents says about each chapter, without fetching any of them
+    pub fn fetch_chapter_list(&self, uri: Uri, novel_info: &NovelInfo)
+    -> NovelResult< Vec<ChapterManifest> > {
         match self {
-            Self::Kakuyomu => self::kakuyomu::make_kakuyomu_novel(uri),
-            Self::Syosetu => self::syosetu::make_syosetu_novel(uri),
+            Self::Kakuyomu => self::kakuyomu::fetch_kakuyomu_chapter_list(uri, novel_info),
+            Self::Syosetu => self::syosetu::fetch_syosetu_chapter_list(uri, novel_info),
         }
     }
+
+    // The least we can fetch to know that the novel is there and still looks the way we expect
+    pub fn fetch_title(&self, uri: Uri, novel_info: &NovelInfo) -> NovelResult<String> {
+        match self {
+            Self::Kakuyomu => self::kakuyomu::fetch_kakuyomu_title(uri, novel_info),
+            Self::Syosetu => self::syosetu::fetch_syosetu_title(uri, novel_info),
+        }
+    }
+
+    // Only the main pages of the novel, but never any of the chapters
+    pub fn fetch_overview(&self, uri: Uri, novel_info: &NovelInfo)
+    -> NovelResult<NovelOverview> {
+        let mut overview = match self {
+            Self::Kakuyomu => self::kakuyomu::fetch_kakuyomu_overview(uri, novel_info),
+            Self::Syosetu => self::syosetu::fetch_syosetu_overview(uri, novel_info),
+        }?;
+        override_metadata(&mut overview.title, &mut overview.author, novel_info);
+        if novel_info.title_override.is_some() {
+            overview.title_ruby = None;
+        }
+        Ok(overview)
+    }
+
+    // The overview along with the newest chapter's date, still without fetching any chapters
+    pub fn fetch_metadata(&self, uri: Uri, novel_info: &NovelInfo)
+    -> NovelResult<NovelMetadata> {
+        let (mut overview, chapters) = match self {
+            Self::Kakuyomu =>
+                self::kakuyomu::fetch_kakuyomu_overview_and_chapters(&uri, novel_info),
+            Self::Syosetu => self::syosetu::fetch_syosetu_overview_and_chapters(&uri, novel_info),
+        }?;
+        override_metadata(&mut overview.title, &mut overview.author, novel_info);
+        Ok(NovelMetadata::new(overview, &chapters))
+    }
+
+    // This should make as many other web requests as it needs
+    //  The observer hears about every chapter as soon as it's fetched
+    pub fn make_novel(&self, uri: Uri, novel_info: &NovelInfo, observer: &dyn ProgressObserver)
+    -> NovelResult<Novel> {
+        let mut novel = match self {
+            Self::Kakuyomu => self::kakuyomu::make_kakuyomu_novel(uri, novel_info, observer),
+            Self::Syosetu => self::syosetu::make_syosetu_novel(uri, novel_info, observer),
+        }?;
+        apply_novel_info(&mut novel, novel_info);
+        Ok(novel)
+    }
+
+    // Only the main pages, like fetch_overview. The novel doesn't have any chapters in it
+    pub fn make_stub_novel(&self, uri: Uri, novel_info: &NovelInfo)
+    -> NovelResult<(Novel, Vec<ChapterManifest>)> {
+        let (mut novel, chapters) = match self {
+            Self::Kakuyomu => self::kakuyomu::fetch_kakuyomu_stub(uri, novel_info),
+            Self::Syosetu => self::syosetu::fetch_syosetu_stub(uri, novel_info),
+        }?;
+        apply_novel_info(&mut novel, novel_info);
+        Ok((novel, chapters))
+    }
+}
+fn apply_novel_info(novel: &mut Novel, novel_info: &NovelInfo) {
+    override_metadata(&mut novel.title, &mut novel.author, novel_info);
+    if novel_info.title_override.is_some() {
+        novel.title_ruby = None;
+    }
+    if let Some(chapter_counter) = novel_info.chapter_counter.as_ref() {
+        novel.chapter_counter = chapter_counter.clone();
+    }
+    novel.reorder_sections(&novel_info.section_order);
+    novel.move_appendix_chapters(&novel_info.appendix_patterns);
+}
+// Everything else (like the book names) uses these, so they only need replacing here
+fn override_metadata(title: &mut String, author: &mut String, novel_info: &NovelInfo) {
+    if let Some(title_override) = novel_info.title_override.as_ref() {
+        *title = title_override.clone();
+    }
+    if let Some(author_override) = novel_info.author_override.as_ref() {
+        *author = author_override.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kuchiki::traits::*;
+
+    use super::*;
+
+    fn chapter(order_num: u32) -> Chapter {
+        Chapter {
+            name: format!("第{}話", order_num),
+            date: String::new(),
+            order_num,
+            source_url: String::new(),
+            tags: Vec::new(),
+            content: Vec::new(),
+            author_notes: AuthorNotes::default(),
+        }
+    }
+    // Everything that isn't in the extra config is left as its default
+    fn run_info(extra_config: &str) -> RunInfo {
+        toml::from_str(&format!("save_dir = \".\"\nnovels = []\n{}", extra_config))
+            .expect("The config didn't parse")
+    }
+    fn order_nums(chapters: &[Chapter]) -> Vec<u32> {
+        chapters.iter().map(|chapter| chapter.order_num).collect()
+    }
+    // Like the chapters finishing in whatever order their requests came back
+    fn fetch_out_of_order(order_nums: Vec<u32>) -> Vec<Chapter> {
+        let mut chapters: Vec<Chapter> = order_nums.into_iter().map(chapter).collect();
+        chapters.reverse();
+        chapters.swap(0, 1);
+        chapters
+    }
+
+    #[test]
+    fn chapter_pages_escape_markup_characters() {
+        let mut chapter = chapter(1);
+        chapter.name = "A & B <C>".to_string();
+        chapter.tags = vec!["<PR>".to_string()];
+        chapter.content = vec![
+            ContentLine::Line(vec![Content::Span("1 < 2 & 3".to_string())], LineAlign::Start),
+        ];
+        let page = chapter.make_xhtml(&run_info("show_chapter_tags = true"), "話")
+            .write_doc_to(Vec::new())
+            .expect("The page couldn't be written");
+        let page = String::from_utf8(page).expect("The page isn't UTF-8");
+        assert!(!page.contains("<C>") && !page.contains("<PR>"), "Not escaped: {}", page);
+
+        let page_node = kuchiki::parse_html().one(page.as_str());
+        let text_of = |selector| page_node.select_first(selector)
+            .map(|element| element.text_contents())
+            .unwrap_or_else(|_| panic!("{} is missing from {}", selector, page));
+        assert_eq!(text_of("title"), "A & B <C>");
+        assert_eq!(text_of("h1"), "A & B <C>");
+        assert_eq!(text_of(".chapter-tags"), "<PR>");
+        assert_eq!(text_of(CHAPTER_CONTENTS_SELECTOR).trim(), "1 < 2 & 3");
+    }
+    #[test]
+    fn chapters_are_in_order_after_an_out_of_order_fetch() {
+        let contents = assemble_contents(Vec::<Vec<u32>>::new(), vec![1, 2, 3, 4, 5], false,
+            |section| section.is_empty(),
+            |_| panic!("There aren't any sections"),
+            |chapter_infos| Ok(fetch_out_of_order(chapter_infos)),
+        ).expect("The chapters couldn't be put together");
+        match contents {
+            NovelContents::Chapters(chapters) =>
+                assert_eq!(order_nums(&chapters), vec![1, 2, 3, 4, 5]),
+            NovelContents::Sections(_) => panic!("There shouldn't be any sections"),
+        }
+    }
+    #[test]
+    fn chapters_in_each_section_are_in_order_after_an_out_of_order_fetch() {
+        let section_infos = vec![vec![1, 2, 3], vec![4, 5, 6, 7]];
+        let contents = assemble_contents(section_infos, Vec::<u32>::new(), false,
+            |section| section.is_empty(),
+            |section_infos| Ok(section_infos.into_iter()
+                .enumerate()
+                .map(|(i, chapter_infos)| Section {
+                    name: format!("第{}章", i + 1),
+                    description: None,
+                    chapters: fetch_out_of_order(chapter_infos),
+                })
+                .collect()),
+            |_| panic!("Every chapter is in a section"),
+        ).expect("The sections couldn't be put together");
+        match contents {
+            NovelContents::Sections(sections) => {
+                assert_eq!(order_nums(&sections[0].chapters), vec![1, 2, 3]);
+                assert_eq!(order_nums(&sections[1].chapters), vec![4, 5, 6, 7]);
+            },
+            NovelContents::Chapters(_) => panic!("The sections went missing"),
+        }
+    }
+    #[test]
+    fn chapters_sharing_a_number_keep_the_order_they_came_in() {
+        let mut chapters = vec![chapter(2), chapter(1), chapter(2)];
+        chapters[0].name = "先".to_string();
+        chapters[2].name = "後".to_string();
+        sort_chapters(&mut chapters);
+        let names: Vec<&str> = chapters.iter().map(|chapter| chapter.name.as_str()).collect();
+        assert_eq!(names, vec!["第1話", "先", "後"]);
+    }
 }
@@ -0,0 +1,81 @@
+use std::{
+    fs,
+    path::{Path},
+};
+
+use crate::{
+    NovelResult,
+    novel::{Chapter, Content, ContentLine, LineAlign, Novel},
+};
+
+// Aozora Bunko's own files always use CRLF, so the readers made for them expect it too
+const LINE_END: &'static str = "\r\n";
+
+// The whole book goes into one text file, like every work on Aozora Bunko
+pub fn write_aozora(novel: &Novel, chapters: &[&Chapter], text_path: &Path) -> NovelResult<()> {
+    let mut aozora = String::new();
+    push_line(&mut aozora, &novel.title);
+    push_line(&mut aozora, &novel.author);
+    push_line(&mut aozora, "");
+    for (i, chapter) in chapters.iter().enumerate() {
+        if i > 0 {
+            push_line(&mut aozora, "［＃改ページ］");
+        }
+        push_chapter(&mut aozora, chapter);
+    }
+    fs::write(text_path, aozora)?;
+    Ok(())
+}
+
+fn push_chapter(aozora: &mut String, chapter: &Chapter) {
+    push_line(aozora, &format!("［＃５字下げ］{}［＃「{}」は中見出し］", &chapter.name,
+        &chapter.name));
+    push_line(aozora, &format!("［＃地付き］{}", &chapter.date));
+    push_line(aozora, "");
+    for content_line in chapter.content.iter() {
+        match content_line {
+            ContentLine::Line(contents, align) => {
+                let line = aozora_contents(contents);
+                match align {
+                    LineAlign::Start => push_line(aozora, &line),
+                    // There isn't a note for centering a line, so it gets indented like a heading
+                    LineAlign::Center => push_line(aozora, &format!("［＃５字下げ］{}", line)),
+                    LineAlign::Right => push_line(aozora, &format!("［＃地付き］{}", line)),
+                }
+            },
+            ContentLine::Blank => push_line(aozora, ""),
+            ContentLine::List { ordered, items } => {
+                for (i, item) in items.iter().enumerate() {
+                    let marker = if *ordered {
+                        format!("{}．", i + 1)
+                    } else {
+                        "・".to_string()
+                    };
+                    push_line(aozora, &format!("{}{}", marker, aozora_contents(item)));
+                }
+            },
+            // Plain text can't line up the columns, so each row's cells are only spaced apart
+            ContentLine::Table(rows) => {
+                for row in rows.iter() {
+                    let cells: Vec<String> = row.cells.iter()
+                        .map(|cell| aozora_contents(cell))
+                        .collect();
+                    push_line(aozora, &cells.join("　"));
+                }
+            },
+        }
+    }
+}
+fn aozora_contents(contents: &[Content]) -> String {
+    contents.iter()
+        .map(|content| match content {
+            Content::Span(text) => text.clone(),
+            // The ｜ marks exactly where the base starts, so it never takes too much of the text
+            Content::Ruby { main, above } => format!("｜{}《{}》", main, above),
+        })
+        .collect()
+}
+fn push_line(aozora: &mut String, line: &str) {
+    aozora.push_str(line);
+    aozora.push_str(LINE_END);
+}
@@ -1,4 +1,21 @@
-use ebook_builder::xml_tree::xhtml_prelude::*;
+use std::{
+    fs::{File},
+    io::{Read},
+    path::{Path},
+};
+use kuchiki::traits::*;
+use serde::{Deserialize};
+use zip::{ZipArchive};
+
+use ebook_builder::{
+    Book, EBookType, FileType, ReadingDir,
+    xml_tree::xhtml_prelude::*,
+};
+
+use crate::{
+    NovelResult,
+    novel::{ReadingDirection},
+};
 
 pub const NOVEL_CSS: &'static str = r#"\
 body {
@@ -16,12 +33,191 @@ h1, h2, h3, h4, div, p, ol, ul, li {
 #novel_chapter_contents {
 	line-height: 1.8;
 }
+.chapter-tags, .novel-keywords, .section-description {
+	font-size: small;
+}
+#novel_chapter_contents ul, #novel_chapter_contents ol {
+	margin-block: 1em;
+	padding-inline-start: 2em;
+}
+#novel_chapter_contents table {
+	margin-block: 1em;
+	border-collapse: collapse;
+}
+#novel_chapter_contents th, #novel_chapter_contents td {
+	padding: 0.2em 0.5em;
+	border: 1px solid gray;
+}
+#novel_chapter_contents th {
+	font-weight: bold;
+}
+.author-note {
+	font-size: small;
+	margin-block: 2em;
+	padding: 0.5em;
+	border: 1px solid gray;
+}
+.separator {
+	margin-block-start: 40%;
+	text-align: center;
+	font-size: x-large;
+}
+.toc-link {
+	margin-top: 2em;
+	text-align: end;
+}
+.chapter-start, .section-start {
+	page-break-before: always;
+	break-before: page;
+}
 .center {
     width: 100%;
     text-align: center;
 }
+.align-center {
+	text-align: center;
+}
+.align-right {
+	text-align: right;
+	text-align: end;
+}
 "#;
 pub const NOVEL_CSS_NAME: &'static str = "novel.css";
+// ebook_builder puts the pages and the resources (like the CSS) in folders next to each other
+//  verify_links checks this against the saved book, since nothing else would notice it changing
+const RESOURCE_LINK_PREFIX: &'static str = "../resources/";
+// The font takes over the family name that the body already asks for, so nothing else changes
+pub fn novel_css(font_file_name: Option<&str>, reading_dir: ReadingDirection) -> String {
+    let mut novel_css = NOVEL_CSS.to_string();
+    if let Some(font_file_name) = font_file_name {
+        novel_css.push_str(&format!(
+            "@font-face {{\n\tfont-family: serif-ja;\n\tsrc: url(\"{}\");\n}}\n",
+            font_file_name));
+    }
+    // Goes after everything else so that it wins over the vertical writing mode
+    if let ReadingDirection::Ltr = reading_dir {
+        novel_css.push_str(HORIZONTAL_CSS);
+    }
+    novel_css
+}
+const HORIZONTAL_CSS: &'static str = r#"#novel_chapter {
+	writing-mode: horizontal-tb;
+	-webkit-writing-mode: horizontal-tb;
+	-epub-writing-mode: horizontal-tb;
+}
+"#;
+
+// Control characters can't be in XML at all (not even escaped), but some sites still send them
+//  Markup characters are left alone, since ebook_builder escapes them when it writes the book
+pub fn xml_text(text: &str) -> String {
+    text.chars()
+        .filter(|&c| match c {
+            '\t' | '\n' | '\r' => true,
+            '\u{FFFE}' | '\u{FFFF}' => false,
+            c => c >= ' ',
+        })
+        .collect()
+}
+
+// How every page gets written out, once ebook_builder has turned it into XHTML
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum XhtmlFormat {
+    // Exactly what ebook_builder gives back
+    AsIs,
+    // Every block (like <p> or <div>) starts its own line, indented by how deep it is
+    Pretty,
+    // Leaves out the whitespace between the tags that's only there to lay them out
+    Minify,
+}
+impl Default for XhtmlFormat {
+    fn default() -> Self { Self::AsIs }
+}
+impl XhtmlFormat {
+    fn format(self, page_bytes: Vec<u8>) -> Vec<u8> {
+        let page_text = match self {
+            Self::AsIs => return page_bytes,
+            Self::Pretty | Self::Minify => String::from_utf8_lossy(&page_bytes).to_string(),
+        };
+        let mut formatted = String::with_capacity(page_text.len());
+        let mut depth = 0;
+        // Only a block that had other blocks in it needs its end tag on a new line
+        let mut after_block = false;
+        for token in XmlTokens::new(&page_text) {
+            match token {
+                // Whitespace can matter between inline tags, but it never has a new line in it
+                XmlToken::Text(text) if text.trim().is_empty() && text.contains('\n') => (),
+                XmlToken::Text(text) => {
+                    formatted.push_str(text);
+                    after_block = false;
+                },
+                XmlToken::Tag(tag) => {
+                    let is_declaration = tag.starts_with("<?") || tag.starts_with("<!");
+                    let is_end = tag.starts_with("</");
+                    let tag_name: String = tag.trim_start_matches(|c| c == '<' || c == '/')
+                        .chars()
+                        .take_while(|c| c.is_ascii_alphanumeric())
+                        .collect();
+                    let is_block = is_declaration || BLOCK_TAGS.contains(&tag_name.as_str());
+                    if let (Self::Pretty, true) = (self, is_block) {
+                        if is_end {
+                            depth = depth.max(1) - 1;
+                        }
+                        if !formatted.is_empty() && (!is_end || after_block) {
+                            formatted.push('\n');
+                            formatted.push_str(&"  ".repeat(depth));
+                        }
+                        if !is_end && !is_declaration && !tag.ends_with("/>") {
+                            depth += 1;
+                        }
+                    }
+                    formatted.push_str(tag);
+                    after_block = is_block;
+                },
+            }
+        }
+        if let Self::Pretty = self {
+            formatted.push('\n');
+        }
+        formatted.into_bytes()
+    }
+}
+const BLOCK_TAGS: [&'static str; 23] = [
+    "html", "head", "title", "meta", "link", "body", "div", "p", "h1", "h2", "h3", "h4", "h5",
+    "h6", "ol", "ul", "li", "table", "thead", "tbody", "tr", "th", "td",
+];
+enum XmlToken<'a> {
+    // Everything from < to >
+    Tag(&'a str),
+    Text(&'a str),
+}
+// Text that has been escaped never has a < in it, so every one starts a tag
+struct XmlTokens<'a> {
+    rest: &'a str,
+}
+impl<'a> XmlTokens<'a> {
+    fn new(xml_text: &'a str) -> XmlTokens<'a> { XmlTokens { rest: xml_text } }
+}
+impl<'a> Iterator for XmlTokens<'a> {
+    type Item = XmlToken<'a>;
+    fn next(&mut self) -> Option<XmlToken<'a>> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let token_end = if self.rest.starts_with('<') {
+            self.rest.find('>').map_or(self.rest.len(), |tag_end| tag_end + 1)
+        } else {
+            self.rest.find('<').unwrap_or(self.rest.len())
+        };
+        let (token, rest) = self.rest.split_at(token_end);
+        self.rest = rest;
+        if token.starts_with('<') {
+            Some(XmlToken::Tag(token))
+        } else {
+            Some(XmlToken::Text(token))
+        }
+    }
+}
 
 // Starts the XHTML tree with the <head> completely filled out
 pub fn start_xhtml(head_title: &str, body: BodyTag) -> HtmlTag {
@@ -30,13 +226,307 @@ pub fn start_xhtml(head_title: &str, body: BodyTag) -> HtmlTag {
         .ns("epub", "http://www.idpf.org/2007/ops")
         .attr_lang("ja")
         .append_child(HeadTag::new()
-            .append_child(TitleTag::new().text(head_title))
+            .append_child(TitleTag::new().text(&xml_text(head_title)))
             .append_child(MetaTag::new().attr_charset("UTF-8"))
             .append_child(LinkTag::new()
                 .attr_rel("stylesheet")
-                .attr_href("../resources/novel.css")
+                .attr_href(&format!("{}{}", RESOURCE_LINK_PREFIX, NOVEL_CSS_NAME))
                 .attr_type("text/css")
             )
         )
         .append_child(body)
 }
+
+const XML_DECLARATION: &'static str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>";
+// Some strict readers won't open a page without the declaration, even with the <meta charset>
+//  Only adds it when ebook_builder didn't already write one
+fn with_xml_declaration(page_bytes: Vec<u8>) -> Vec<u8> {
+    if page_bytes.starts_with(b"<?xml") {
+        return page_bytes;
+    }
+    let mut declared_bytes = Vec::with_capacity(XML_DECLARATION.len() + 1 + page_bytes.len());
+    declared_bytes.extend_from_slice(XML_DECLARATION.as_bytes());
+    declared_bytes.push(b'\n');
+    declared_bytes.extend(page_bytes);
+    declared_bytes
+}
+
+// Only the text inside of the selected elements counts, like a chapter's contents without its name
+pub fn has_text(page: HtmlTag, selector: &str) -> NovelResult<bool> {
+    let page_bytes: Vec<u8> = page.write_doc_to(Vec::new())?;
+    let page_node = kuchiki::parse_html().one(String::from_utf8_lossy(&page_bytes).to_string());
+    Ok(match page_node.select(selector) {
+        Ok(mut elements) => elements.any(|element| !element.text_contents().trim().is_empty()),
+        Err(()) => false,
+    })
+}
+
+// Everything that goes into a book goes through here, so that every page gets finished the same way
+#[derive(Clone)]
+pub struct NovelBook {
+    book: Book,
+    xhtml_format: XhtmlFormat,
+}
+impl NovelBook {
+    pub fn new(title: &str, reading_dir: ReadingDirection, xhtml_format: XhtmlFormat)
+    -> NovelBook {
+        NovelBook {
+            book: Book::new(&xml_text(title), reading_dir.reading_dir(), "ja"),
+            xhtml_format,
+        }
+    }
+
+    pub fn add_author(&mut self, author: &str) {
+        self.book.add_author(&xml_text(author), None);
+    }
+
+    // The page always starts a new entry in the table of contents
+    pub fn add_page(&mut self, file_name: &str, page: HtmlTag, nav_name: &str)
+    -> NovelResult<()> {
+        self.add_unlisted_page(file_name, page)?;
+        self.book.mark_as_chapter_start(&xml_text(nav_name));
+        Ok(())
+    }
+    // Stays a part of whatever came before it in the table of contents
+    pub fn add_unlisted_page(&mut self, file_name: &str, page: HtmlTag) -> NovelResult<()> {
+        let page_bytes = with_xml_declaration(
+            self.xhtml_format.format(page.write_doc_to(Vec::new())?));
+        self.book.add_file_as_bytes(file_name, &page_bytes, FileType::Xhtml);
+        Ok(())
+    }
+    // For anything that isn't a page, like the CSS
+    pub fn add_resource(&mut self, file_name: &str, bytes: &[u8], file_type: FileType) {
+        self.book.add_file_as_bytes(file_name, bytes, file_type);
+    }
+
+    pub fn save_to_file(&mut self, ebook_type: EBookType, book_path: &Path) -> NovelResult<()> {
+        self.book.save_to_file(ebook_type, book_path, true)?;
+        Ok(())
+    }
+}
+
+// Every link in the pages of a saved book that doesn't go to one of its files
+//  ebook_builder decides which folder each file goes in, so only the paths in the book are trusted
+pub fn broken_links(book_path: &Path) -> NovelResult< Vec<String> > {
+    let mut archive = ZipArchive::new(File::open(book_path)?)?;
+    let mut book_files = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        book_files.push(archive.by_index(i)?.name().to_string());
+    }
+    let mut broken_links = Vec::new();
+    for page_file in book_files.iter().filter(|book_file| is_page_file(book_file)) {
+        let mut page_text = String::new();
+        archive.by_name(page_file)?.read_to_string(&mut page_text)?;
+        let page_node = kuchiki::parse_html().one(page_text);
+        let linking_elements = match page_node.select("[href], [src]") {
+            Ok(linking_elements) => linking_elements,
+            Err(()) => continue,
+        };
+        for element in linking_elements {
+            let attributes = element.attributes.borrow();
+            let link = match attributes.get("href").or(attributes.get("src")) {
+                Some(link) if !is_external_link(link) => link,
+                _ => continue,
+            };
+            let goes_somewhere = resolve_book_link(page_file, link)
+                .map_or(false, |target| book_files.contains(&target));
+            if !goes_somewhere {
+                broken_links.push(format!("{} links to {}", page_file, link));
+            }
+        }
+    }
+    Ok(broken_links)
+}
+fn is_page_file(book_file: &str) -> bool {
+    book_file.ends_with(".xhtml") || book_file.ends_with(".html")
+}
+fn is_external_link(link: &str) -> bool {
+    link.contains("://") || link.starts_with("mailto:")
+}
+// Gives back the name of the file that the link points to, from the page that it's in
+//  Going above the top of the book can't point to anything
+fn resolve_book_link(page_file: &str, link: &str) -> Option<String> {
+    let link_path = link.split('#').next().unwrap_or("");
+    if link_path.is_empty() {
+        return Some(page_file.to_string());
+    }
+    let mut segments: Vec<&str> = page_file.split('/').collect();
+    // Only the folders of the page are left
+    segments.pop();
+    for segment in link_path.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." => {
+                segments.pop()?;
+            },
+            segment => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page_text(page: HtmlTag) -> String {
+        let page_bytes = page.write_doc_to(Vec::new()).expect("The page couldn't be written");
+        String::from_utf8(with_xml_declaration(page_bytes)).expect("The page isn't UTF-8")
+    }
+
+    #[test]
+    fn every_page_starts_with_the_xml_declaration() {
+        let page = page_text(start_xhtml("第一話", BodyTag::new()
+            .append_child(PTag::new().text("本文"))));
+        assert!(page.starts_with(XML_DECLARATION), "The page starts with {:?}",
+            page.lines().next());
+        // Only once, even if ebook_builder already wrote one
+        assert_eq!(page.matches("<?xml").count(), 1);
+
+        let page_node = kuchiki::parse_html().one(page.as_str());
+        let title = page_node.select_first("title").expect("The page lost its <title>");
+        assert_eq!(title.text_contents(), "第一話");
+        let line = page_node.select_first("body > p").expect("The page lost its <p>");
+        assert_eq!(line.text_contents(), "本文");
+    }
+    #[test]
+    fn text_with_markup_characters_comes_back_out_the_same() {
+        let title = "A & B <C> \"D\" 'E'";
+        let page = page_text(start_xhtml(title, BodyTag::new()
+            .append_child(PTag::new().text(&xml_text(title)))));
+        assert!(!page.contains("<C>"), "The < wasn't escaped in {}", page);
+        assert!(!page.contains("& B"), "The & wasn't escaped in {}", page);
+
+        let page_node = kuchiki::parse_html().one(page.as_str());
+        assert_eq!(page_node.select_first("title").unwrap().text_contents(), title);
+        assert_eq!(page_node.select_first("body > p").unwrap().text_contents(), title);
+    }
+    #[test]
+    fn markup_characters_are_escaped_only_once_in_the_saved_book() {
+        let title = "A & B <C>";
+        let mut book = NovelBook::new(title, ReadingDirection::Rtl, XhtmlFormat::AsIs);
+        book.add_author(title);
+        book.add_page("chapter-1.xhtml", start_xhtml(title, BodyTag::new()
+            .append_child(PTag::new().text(&xml_text(title)))), title).unwrap();
+        let book_path = save_book(&mut book, "markup_characters_are_escaped");
+
+        let mut archive = ZipArchive::new(File::open(&book_path).unwrap()).unwrap();
+        let mut book_texts = Vec::with_capacity(archive.len());
+        for i in 0..archive.len() {
+            let mut book_file = archive.by_index(i).unwrap();
+            let mut book_text = String::new();
+            if book_file.read_to_string(&mut book_text).is_ok() {
+                book_texts.push((book_file.name().to_string(), book_text));
+            }
+        }
+        std::fs::remove_file(&book_path).unwrap();
+
+        let mut found_files = 0;
+        for (book_file, book_text) in book_texts.iter() {
+            assert!(!book_text.contains("&amp;amp;") && !book_text.contains("&amp;lt;"),
+                "{} is escaped twice: {}", book_file, book_text);
+            assert!(!book_text.contains("<C>"), "{} isn't escaped: {}", book_file, book_text);
+            // The title, the author, the navigation name and the page text all come back out
+            let text_node = kuchiki::parse_html().one(book_text.as_str());
+            if text_node.descendants().text_nodes().any(|text| *text.borrow() == title) {
+                found_files += 1;
+            }
+        }
+        // At least the page and the package (and the table of contents, if it's separate)
+        assert!(found_files >= 2, "The title only came back out of {} files", found_files);
+    }
+    #[test]
+    fn control_characters_are_dropped() {
+        assert_eq!(xml_text("一\u{0}二\u{8}三\t四\u{FFFF}"), "一二三\t四");
+    }
+    // Saved for real, so that the files are wherever ebook_builder puts them
+    fn save_book(book: &mut NovelBook, test_name: &str) -> std::path::PathBuf {
+        let book_path = std::env::temp_dir().join(format!("{}_{}.epub", test_name,
+            std::process::id()));
+        book.save_to_file(EBookType::Epub, &book_path).expect("The book couldn't be saved");
+        book_path
+    }
+    fn page_with_links(links: &[&str]) -> HtmlTag {
+        start_xhtml("ページ", links.iter().fold(BodyTag::new(), |body, link| {
+            body.append_child(PTag::new().append_child(ATag::new().attr_href(link).text("リンク")))
+        }))
+    }
+
+    #[test]
+    fn links_are_checked_against_the_files_in_the_saved_book() {
+        let mut book = NovelBook::new("本", ReadingDirection::Rtl, XhtmlFormat::AsIs);
+        book.add_page("chapter-1.xhtml", page_with_links(&[
+            "chapter-2.xhtml",
+            "chapter-2.xhtml#top",
+            "missing.xhtml",
+            "https://kakuyomu.jp/works/1",
+        ]), "第1話").unwrap();
+        book.add_page("chapter-2.xhtml", page_with_links(&["chapter-1.xhtml"]), "第2話").unwrap();
+        let book_path = save_book(&mut book, "links_are_checked");
+        let broken_links = broken_links(&book_path).expect("The book couldn't be read back");
+        std::fs::remove_file(&book_path).unwrap();
+
+        let page_links: Vec<&String> = broken_links.iter()
+            .filter(|broken_link| broken_link.contains(".xhtml links to chapter-")
+                || broken_link.ends_with("links to missing.xhtml"))
+            .collect();
+        assert_eq!(page_links.len(), 1, "Found {:?}", broken_links);
+        assert!(page_links[0].ends_with("chapter-1.xhtml links to missing.xhtml"));
+        assert!(!broken_links.iter().any(|broken_link| broken_link.contains("://")));
+    }
+    // Named like the pages that a book (or an omnibus) really gets
+    const PAGE_FILES: [&'static str; 6] = [
+        "title-cover.xhtml",
+        "contents.xhtml",
+        "section-cover-1.xhtml",
+        "chapter-001.xhtml",
+        "novel-2-chapter-12.xhtml",
+        "colophon.xhtml",
+    ];
+    #[test]
+    fn the_css_is_reachable_from_every_page_of_the_saved_book() {
+        let mut book = NovelBook::new("本", ReadingDirection::Rtl, XhtmlFormat::AsIs);
+        let novel_css = novel_css(None, ReadingDirection::Rtl);
+        book.add_resource(NOVEL_CSS_NAME, novel_css.as_bytes(), FileType::Css);
+        for page_file in PAGE_FILES.iter() {
+            book.add_page(page_file, start_xhtml("ページ", BodyTag::new()), "ページ").unwrap();
+        }
+        let book_path = save_book(&mut book, "the_css_is_reachable");
+
+        let mut archive = ZipArchive::new(File::open(&book_path).unwrap()).unwrap();
+        let book_files: Vec<String> = (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_string())
+            .collect();
+        let css_link = format!("{}{}", RESOURCE_LINK_PREFIX, NOVEL_CSS_NAME);
+        for page_name in PAGE_FILES.iter() {
+            // Wherever ebook_builder put the page, the link from there has to find the CSS
+            let page_file = book_files.iter()
+                .find(|book_file| book_file.ends_with(&format!("/{}", page_name))
+                    || book_file == page_name)
+                .unwrap_or_else(|| panic!("{} isn't in {:?}", page_name, book_files));
+            let css_file = resolve_book_link(page_file, &css_link)
+                .unwrap_or_else(|| panic!("{} goes above the top of the book", &css_link));
+            assert!(css_file.ends_with(NOVEL_CSS_NAME));
+            assert!(book_files.contains(&css_file), "{} isn't in {:?}", css_file, book_files);
+        }
+        let broken_links = broken_links(&book_path).unwrap();
+        std::fs::remove_file(&book_path).unwrap();
+        assert_eq!(broken_links, Vec::<String>::new());
+    }
+    #[test]
+    fn links_are_resolved_from_the_folder_of_their_page() {
+        assert_eq!(resolve_book_link("OEBPS/text/a.xhtml", "b.xhtml").as_ref()
+            .map(String::as_str), Some("OEBPS/text/b.xhtml"));
+        assert_eq!(resolve_book_link("OEBPS/text/a.xhtml", "../resources/novel.css").as_ref()
+            .map(String::as_str), Some("OEBPS/resources/novel.css"));
+        assert_eq!(resolve_book_link("OEBPS/text/a.xhtml", "#top").as_ref()
+            .map(String::as_str), Some("OEBPS/text/a.xhtml"));
+        assert_eq!(resolve_book_link("a.xhtml", "../novel.css"), None);
+    }
+
+    #[test]
+    fn a_declaration_that_is_already_there_is_kept_as_it_is() {
+        let page_bytes = b"<?xml version=\"1.0\"?><html></html>".to_vec();
+        assert_eq!(with_xml_declaration(page_bytes.clone()), page_bytes);
+    }
+}
@@ -1,15 +1,20 @@
-mod content;
+pub mod content;
 
-use kuchiki::{ElementData, NodeDataRef};
+use std::{
+    mem,
+};
+use kuchiki::{ElementData, NodeDataRef, NodeRef};
 use isahc::http::{Uri};
 use rayon::prelude::*;
 
 use crate::{
-    NovelError, NovelResult, NovelComponent,
+    NovelError, NovelInfo, NovelResult, NovelComponent,
     novel::{
-        Novel, Section, Chapter, NovelStatus, NovelContents,
-        novel_utils,
+        self, Content, Novel, NovelOverview, Section, Chapter, NovelStatus, AuthorNotes,
+        SelectorOverrides, novel_utils,
+        manifest::{ChapterManifest},
     },
+    progress::{ProgressObserver},
     traverser::{TreeTraverser},
 };
 
@@ -35,66 +40,199 @@ pub fn is_kakuyomu_novel(uri: &Uri) -> bool {
     }
 }
 
+// Lists that readers put together out of other people's works, like /users/name/collections/123
+pub fn is_kakuyomu_collection(uri: &Uri) -> bool {
+    uri.host() == Some(HOST_NAME) && uri.path().starts_with("/users/")
+        && uri.path().contains("/collections/")
+}
+// Episodes are linked as well, so only the works themselves get kept
+const COLLECTION_WORK_SELECTOR: &'static str = "a[href^=\"/works/\"]";
+// The URL of every work in the collection, in the same order that the collection has them
+pub fn fetch_kakuyomu_collection(uri: &Uri) -> NovelResult< Vec<String> > {
+    let node = crate::fetch_page(uri)?;
+    let mut work_urls: Vec<String> = Vec::new();
+    if let Ok(work_links) = node.select(COLLECTION_WORK_SELECTOR) {
+        for work_link in work_links {
+            let href = match work_link.attributes.borrow().get("href") {
+                Some(href) => href.to_string(),
+                None => continue,
+            };
+            let work_id = href.trim_start_matches("/works/")
+                .split(|c| c == '?' || c == '#')
+                .next()
+                .unwrap_or("")
+                .trim_end_matches('/');
+            if work_id.is_empty() || work_id.contains('/') {
+                continue;
+            }
+            let work_url = make_uri(&format!("/works/{}", work_id))?.to_string();
+            if !work_urls.contains(&work_url) {
+                work_urls.push(work_url);
+            }
+        }
+    }
+    Ok(work_urls)
+}
+
 const TITLE_SELECTOR: &'static str = "#workTitle > a";
+// Some layouts (like a cached page) have the text without the link, so the whole container is used
+const TITLE_FALLBACK_SELECTOR: &'static str = "#workTitle";
+pub fn fetch_kakuyomu_title(uri: Uri, novel_info: &NovelInfo) -> NovelResult<String> {
+    novel_utils::fetch_title(&uri,
+        &[novel_info.selectors.title(TITLE_SELECTOR), TITLE_FALLBACK_SELECTOR])
+}
 const AUTHOR_SELECTOR: &'static str = "#workAuthor-activityName > a";
+const AUTHOR_FALLBACK_SELECTOR: &'static str = "#workAuthor-activityName";
+const SYNOPSIS_SELECTOR: &'static str = "#introduction";
 const STATUS_SELECTOR: &'static str = "div#workInformationList > dl > dd:nth-child(2)";
+const INFO_LIST_SELECTOR: &'static str = "div#workInformationList > dl > dd";
 const SECTION_SELECTOR: &'static str = "li.widget-toc-chapter > span";
+// Only a few novels have these under the section's name
+const SECTION_DESCRIPTION_SELECTOR: &'static str = "li.widget-toc-chapter > p";
 const CHAPTER_SELECTOR: &'static str = "li.widget-toc-episode > a";
 const CHAPTER_NAME_SELECTOR: &'static str = "span.widget-toc-episode-titleLabel";
 const CHAPTER_DATE_SELECTOR: &'static str = "time.widget-toc-episode-datePublished";
+// These are found in the whole list item, since they can be on either side of the link
+const CHAPTER_TAG_SELECTOR: &'static str = ".widget-toc-episode-label";
+// Episodes that are only scheduled get listed with one of these as a class of the link or
+//  the list item. They don't have a published date either, but that alone isn't enough to skip one
+const UNPUBLISHED_CLASS_MARKERS: [&'static str; 3] = ["reservation", "unpublished", "scheduled"];
 
-pub fn make_kakuyomu_novel(uri: Uri) -> NovelResult<Novel> {
-    let node = crate::fetch_page(&uri)?;
-    let mut main_page_data = TreeTraverser::new(node, MainPageData::default())
-        .add_hook(TITLE_SELECTOR, None, MainPageData::get_title)?
-        .add_hook(AUTHOR_SELECTOR, None, MainPageData::get_author)?
-        .add_hook(STATUS_SELECTOR, None, MainPageData::get_status)?
-        .add_hook(SECTION_SELECTOR, None, MainPageData::get_section)?
-        .add_hook(CHAPTER_SELECTOR, None, MainPageData::get_chapter)?
-        .traverse();
-    // Since we won't encounter another section (if there were any) to move the chapters
-    main_page_data.move_chapters_to_section();
+pub fn make_kakuyomu_novel(uri: Uri, novel_info: &NovelInfo, observer: &dyn ProgressObserver)
+-> NovelResult<Novel> {
+    let selectors = &novel_info.selectors;
+    let mut main_page_data = fetch_main_page(&uri, novel_info)?;
+    let content_selectors = selectors.content(&content::CONTENT_SELECTORS);
 
-    let title = main_page_data.title.ok_or(NovelError::ComponentMissing(NovelComponent::Title))?;
-    let author = main_page_data.author.ok_or(NovelError::ComponentMissing(NovelComponent::Author))?;
-    let status = main_page_data.status.ok_or(NovelError::ComponentMissing(NovelComponent::Status))?;
-    let contents = {
-        if main_page_data.sections.is_empty() {
-            if main_page_data.chapters.is_empty() {
-                return Err(NovelError::ComponentMissing(NovelComponent::Chapter));
-            }
-            let chapters = fetch_chapters(main_page_data.chapters)?;
-            NovelContents::Chapters(chapters)
-        } else {
-            for section in main_page_data.sections.iter() {
-                if section.chapters.is_empty() {
-                    return Err(NovelError::ComponentMissing(NovelComponent::ChapterUnderSection));
-                }
-            }
-            let sections = fetch_sections(main_page_data.sections)?;
-            NovelContents::Sections(sections)
-        }
-    };
+    let overview = main_page_data.take_overview()?;
+    main_page_data.exclude_tagged_chapters(&novel_info.exclude_tags);
+    if let Some(latest) = novel_info.latest {
+        novel::keep_latest_chapters(&mut main_page_data.sections, &mut main_page_data.chapters,
+            latest, SectionInfo::chapters_mut);
+    }
+    let contents = novel::assemble_contents(main_page_data.sections, main_page_data.chapters,
+        novel_info.drop_empty_sections,
+        |section| section.chapters.is_empty(),
+        |sections| fetch_sections(sections, &content_selectors, observer),
+        |chapters| fetch_chapters(chapters, &content_selectors, observer),
+    )?;
 
     Ok(Novel {
-        title,
-        author,
-        status,
+        title: overview.title,
+        title_ruby: overview.title_ruby,
+        author: overview.author,
+        status: overview.status,
+        ratings: overview.ratings,
+        keywords: overview.keywords,
         source_url: uri.to_string(),
+        chapter_counter: "部分目".to_string(),
+        reading_dir: novel_info.reading_dir,
         contents,
     })
 }
 
+pub fn fetch_kakuyomu_overview(uri: Uri, novel_info: &NovelInfo)
+-> NovelResult<NovelOverview> {
+    fetch_main_page(&uri, novel_info)?.take_overview()
+}
+
+pub fn fetch_kakuyomu_chapter_list(uri: Uri, novel_info: &NovelInfo)
+-> NovelResult< Vec<ChapterManifest> > {
+    let mut main_page_data = fetch_main_page(&uri, novel_info)?;
+    main_page_data.exclude_tagged_chapters(&novel_info.exclude_tags);
+    main_page_data.into_chapter_list()
+}
+
+// Both only need the main page, so it only gets fetched once
+pub fn fetch_kakuyomu_overview_and_chapters(uri: &Uri, novel_info: &NovelInfo)
+-> NovelResult<(NovelOverview, Vec<ChapterManifest>)> {
+    let mut main_page_data = fetch_main_page(uri, novel_info)?;
+    let overview = main_page_data.take_overview()?;
+    main_page_data.exclude_tagged_chapters(&novel_info.exclude_tags);
+    Ok((overview, main_page_data.into_chapter_list()?))
+}
+
+// The novel without any chapters, along with the list of the chapters that it has
+pub fn fetch_kakuyomu_stub(uri: Uri, novel_info: &NovelInfo)
+-> NovelResult<(Novel, Vec<ChapterManifest>)> {
+    let (overview, chapters) = fetch_kakuyomu_overview_and_chapters(&uri, novel_info)?;
+    let novel = Novel {
+        title: overview.title,
+        title_ruby: overview.title_ruby,
+        author: overview.author,
+        status: overview.status,
+        ratings: overview.ratings,
+        keywords: overview.keywords,
+        source_url: uri.to_string(),
+        chapter_counter: "部分目".to_string(),
+        reading_dir: novel_info.reading_dir,
+        contents: novel::NovelContents::Chapters(Vec::new()),
+    };
+    Ok((novel, chapters))
+}
+
+fn fetch_main_page(uri: &Uri, novel_info: &NovelInfo) -> NovelResult<MainPageData> {
+    let node = crate::fetch_page(uri)?;
+    novel_utils::check_login_wall(&node, uri, novel_info.selectors.title(TITLE_SELECTOR))?;
+    let mut main_page_data = parse_main_page(node, &novel_info.selectors)?;
+    main_page_data.name_unnamed_chapters(&novel_info.fallback_chapter_name);
+    Ok(main_page_data)
+}
+// Chapters with an empty name in the table of contents are left that way
+pub fn parse_main_page(node: NodeRef, selectors: &SelectorOverrides)
+-> NovelResult<MainPageData> {
+    let mut main_page_data = TreeTraverser::new(node, MainPageData::default())
+        .add_hook(selectors.title(TITLE_SELECTOR), None, MainPageData::get_title)?
+        .add_hook(selectors.author(AUTHOR_SELECTOR), None, MainPageData::get_author)?
+        .add_hook(TITLE_FALLBACK_SELECTOR, None, MainPageData::get_title_fallback)?
+        .add_hook(AUTHOR_FALLBACK_SELECTOR, None, MainPageData::get_author_fallback)?
+        .add_hook(SYNOPSIS_SELECTOR, None, MainPageData::get_synopsis)?
+        .add_hook(STATUS_SELECTOR, None, MainPageData::get_status)?
+        .add_hook(INFO_LIST_SELECTOR, None, MainPageData::get_stated_chapter_count)?
+        .add_hook(selectors.section(SECTION_SELECTOR), None, MainPageData::get_section)?
+        .add_hook(selectors.section_description(SECTION_DESCRIPTION_SELECTOR), None,
+            MainPageData::get_section_description)?
+        .add_hook(selectors.chapter(CHAPTER_SELECTOR), None, MainPageData::get_chapter)?
+        .traverse();
+    // Since we won't encounter another section (if there were any) to move the chapters
+    main_page_data.move_chapters_to_section();
+    if main_page_data.unpublished_count > 0 {
+        println!("Skipping {} episodes that aren't published yet",
+            main_page_data.unpublished_count);
+    }
+    Ok(main_page_data)
+}
+
+// Everything that was parsed is left open, so that a saved page can be checked without fetching
 #[derive(Debug, Default)]
-struct MainPageData {
-    title: Option<String>,
-    author: Option<String>,
-    status: Option<NovelStatus>,
-    sections: Vec<SectionInfo>,
-    chapters: Vec<ChapterInfo>,
+pub struct MainPageData {
+    pub title: Option<String>,
+    pub title_ruby: Option< Vec<Content> >,
+    pub author: Option<String>,
+    // Only used when the usual title or author couldn't be found
+    pub title_fallback: Option<String>,
+    pub author_fallback: Option<String>,
+    pub synopsis: Option<String>,
+    pub status: Option<NovelStatus>,
+    // The number of episodes the work page says it has
+    pub stated_chapter_count: Option<u32>,
+    // Chapters only stay here when there aren't any sections
+    pub sections: Vec<SectionInfo>,
+    pub chapters: Vec<ChapterInfo>,
     chapter_count: u32,
+    // Scheduled episodes that are in the table of contents, but can't be read yet
+    pub unpublished_count: u32,
 }
 impl MainPageData {
+    // Every chapter needs a name for the table of contents, even if the site didn't give it one
+    fn name_unnamed_chapters(&mut self, fallback_chapter_name: &str) {
+        let section_chapters = self.sections.iter_mut()
+            .flat_map(|section| section.chapters.iter_mut());
+        for chapter in section_chapters.chain(self.chapters.iter_mut()) {
+            chapter.name = novel_utils::name_or_fallback(mem::take(&mut chapter.name),
+                chapter.order_num, fallback_chapter_name);
+        }
+    }
     fn move_chapters_to_section(&mut self) {
         if let Some(section) = self.sections.last_mut() {
             section.chapters.append(&mut self.chapters);
@@ -104,13 +242,52 @@ impl MainPageData {
         self.chapter_count += 1;
         self.chapter_count
     }
+    fn into_chapter_list(self) -> NovelResult< Vec<ChapterManifest> > {
+        self.sections.into_iter()
+            .flat_map(|section| section.chapters)
+            .chain(self.chapters)
+            .map(ChapterInfo::into_manifest)
+            .collect()
+    }
+    fn take_overview(&mut self) -> NovelResult<NovelOverview> {
+        let parsed_count = self.chapters.len() + self.sections.iter()
+            .map(|section| section.chapters.len())
+            .sum::<usize>();
+        novel_utils::warn_if_chapter_count_differs(self.stated_chapter_count, parsed_count);
+        Ok(NovelOverview {
+            title: self.title.take().or(self.title_fallback.take())
+                .ok_or(NovelError::ComponentMissing(NovelComponent::Title))?,
+            title_ruby: self.title_ruby.take(),
+            author: self.author.take().or(self.author_fallback.take())
+                .ok_or(NovelError::ComponentMissing(NovelComponent::Author))?,
+            status: self.status
+                .ok_or(NovelError::ComponentMissing(NovelComponent::Status))?,
+            synopsis: self.synopsis.take(),
+            ratings: Vec::new(),
+            keywords: Vec::new(),
+            chapter_count: self.chapter_count,
+        })
+    }
 
     fn get_title(&mut self, element: &NodeDataRef<ElementData>) {
-        self.title = Some(element.text_contents());
+        self.title_ruby = novel_utils::get_ruby_text(element);
+        self.title = Some(match self.title_ruby.as_ref() {
+            Some(title_ruby) => novel_utils::base_text(title_ruby),
+            None => element.text_contents(),
+        });
     }
     fn get_author(&mut self, element: &NodeDataRef<ElementData>) {
         self.author = Some(element.text_contents());
     }
+    fn get_title_fallback(&mut self, element: &NodeDataRef<ElementData>) {
+        self.title_fallback = Some(element.text_contents().trim().to_string());
+    }
+    fn get_author_fallback(&mut self, element: &NodeDataRef<ElementData>) {
+        self.author_fallback = Some(element.text_contents().trim().to_string());
+    }
+    fn get_synopsis(&mut self, element: &NodeDataRef<ElementData>) {
+        self.synopsis = Some(element.text_contents().trim().to_string());
+    }
     fn get_status(&mut self, element: &NodeDataRef<ElementData>) {
         let status_text = element.text_contents();
         self.status = Some(match status_text.as_str() {
@@ -119,50 +296,126 @@ impl MainPageData {
             _ => return,
         });
     }
+    // It looks like 123話, but it shares the selector with the rest of the work information
+    fn get_stated_chapter_count(&mut self, element: &NodeDataRef<ElementData>) {
+        let info_text = element.text_contents();
+        if let Some(count_text) = info_text.trim().strip_suffix('話') {
+            if let Some(count) = novel_utils::parse_count(count_text) {
+                self.stated_chapter_count = Some(count);
+            }
+        }
+    }
+
+    fn exclude_tagged_chapters(&mut self, exclude_tags: &[String]) {
+        if exclude_tags.is_empty() {
+            return;
+        }
+        self.chapters.retain(|chapter| !chapter.has_any_tag(exclude_tags));
+        let mut sections = Vec::new();
+        for mut section in self.sections.drain(..) {
+            let had_chapters = !section.chapters.is_empty();
+            section.chapters.retain(|chapter| !chapter.has_any_tag(exclude_tags));
+            if had_chapters && section.chapters.is_empty() {
+                println!("Leaving out {} since all of its chapters were excluded", &section.name);
+            } else {
+                sections.push(section);
+            }
+        }
+        self.sections = sections;
+    }
 
     fn get_section(&mut self, element: &NodeDataRef<ElementData>) {
         self.move_chapters_to_section();
         self.sections.push(SectionInfo {
             name: element.text_contents(),
+            description: None,
             chapters: Vec::new(),
         });
     }
+    fn get_section_description(&mut self, element: &NodeDataRef<ElementData>) {
+        let description = element.text_contents().trim().to_string();
+        if let Some(section) = self.sections.last_mut() {
+            if !description.is_empty() {
+                section.description = Some(description);
+            }
+        }
+    }
     fn get_chapter(&mut self, element: &NodeDataRef<ElementData>) {
         let chapter_node = element.as_node();
+        // They don't get a number, so the chapters after them keep theirs
+        if is_unpublished(chapter_node) {
+            self.unpublished_count += 1;
+            return;
+        }
+        let order_num = self.increment_and_get_chapters();
         let name = chapter_node.select_first(CHAPTER_NAME_SELECTOR).unwrap()
             .text_contents();
-        let date = chapter_node.select_first(CHAPTER_DATE_SELECTOR).unwrap()
-            .text_contents();
-        let date = novel_utils::convert_num_string_to_ja(&date);
+        // The episode can still be read without its date
+        let date = match chapter_node.select_first(CHAPTER_DATE_SELECTOR) {
+            Ok(date_element) => novel_utils::convert_num_string_to_ja(
+                &date_element.text_contents()),
+            Err(_) => {
+                println!("Couldn't find the date for chapter {}", order_num);
+                String::new()
+            },
+        };
+        let tags = match chapter_node.parent() {
+            Some(list_item) => match list_item.select(CHAPTER_TAG_SELECTOR) {
+                Ok(tag_nodes) => tag_nodes
+                    .map(|tag_node| tag_node.text_contents().trim().to_string())
+                    .filter(|tag| !tag.is_empty())
+                    .collect(),
+                Err(_) => Vec::new(),
+            },
+            None => Vec::new(),
+        };
         let attributes = element.attributes.borrow();
         let uri_path = attributes.get("href").unwrap().to_string();
-        let order_num = self.increment_and_get_chapters();
         self.chapters.push(ChapterInfo {
             name,
             date,
             order_num,
+            tags,
             uri_path,
         });
     }
 }
 
+fn is_unpublished(chapter_node: &NodeRef) -> bool {
+    let has_marker = |node: &NodeRef| node.as_element().map_or(false, |element| {
+        // A whole class has to match, so that something like widget-toc-episode-unpublishedLabel
+        //  doesn't count
+        element.attributes.borrow().get("class").map_or(false, |classes| {
+            classes.split_whitespace().any(|class| UNPUBLISHED_CLASS_MARKERS.contains(&class))
+        })
+    });
+    has_marker(chapter_node) || chapter_node.parent().map_or(false, |list_item| {
+        has_marker(&list_item)
+    })
+}
+
 #[derive(Debug, Default)]
-struct SectionInfo {
-    name: String,
-    chapters: Vec<ChapterInfo>,
+pub struct SectionInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub chapters: Vec<ChapterInfo>,
 }
 impl SectionInfo {
-    fn fetch_section(self) -> NovelResult<Section> {
-        let chapters = fetch_chapters(self.chapters)?;
+    fn chapters_mut(&mut self) -> &mut Vec<ChapterInfo> { &mut self.chapters }
+    fn fetch_section(self, content_selectors: &[String], observer: &dyn ProgressObserver)
+    -> NovelResult<Section> {
+        let chapters = fetch_chapters(self.chapters, content_selectors, observer)?;
         Ok(Section {
             name: self.name,
+            description: self.description,
             chapters,
         })
     }
 }
-fn fetch_sections(section_infos: Vec<SectionInfo>) -> NovelResult< Vec<Section> > {
+fn fetch_sections(section_infos: Vec<SectionInfo>, content_selectors: &[String],
+observer: &dyn ProgressObserver) -> NovelResult< Vec<Section> > {
     let section_results: Vec<_> = section_infos.into_par_iter()
-        .map(|section| section.fetch_section())
+        .map(|section| section.fetch_section(content_selectors, observer))
         .collect();
     let mut sections = Vec::new();
     for section in section_results {
@@ -171,27 +424,47 @@ fn fetch_sections(section_infos: Vec<SectionInfo>) -> NovelResult< Vec<Section>
     Ok(sections)
 }
 #[derive(Debug, Default)]
-struct ChapterInfo {
-    name: String,
-    date: String,
-    order_num: u32,
-    uri_path: String,
+pub struct ChapterInfo {
+    pub name: String,
+    pub date: String,
+    pub order_num: u32,
+    pub tags: Vec<String>,
+    pub uri_path: String,
 }
 impl ChapterInfo {
-    fn fetch_chapter(self) -> NovelResult<Chapter> {
+    fn has_any_tag(&self, tags: &[String]) -> bool {
+        self.tags.iter().any(|tag| tags.contains(tag))
+    }
+
+    fn into_manifest(self) -> NovelResult<ChapterManifest> {
+        Ok(ChapterManifest {
+            order_num: self.order_num,
+            name: self.name,
+            date: self.date,
+            source_url: make_uri(&self.uri_path)?.to_string(),
+        })
+    }
+    fn fetch_chapter(self, content_selectors: &[String], observer: &dyn ProgressObserver)
+    -> NovelResult<Chapter> {
         let uri = make_uri(&self.uri_path)?;
-        let content = content::fetch_novel_content(uri)?;
+        let source_url = uri.to_string();
+        let content = content::fetch_novel_content(uri, content_selectors)?;
+        observer.on_chapter_fetched(self.order_num, &self.name);
         Ok(Chapter {
             name: self.name,
             date: self.date,
             order_num: self.order_num,
+            source_url,
+            tags: self.tags,
             content,
+            author_notes: AuthorNotes::default(),
         })
     }
 }
-fn fetch_chapters(chapter_infos: Vec<ChapterInfo>) -> NovelResult< Vec<Chapter> > {
+fn fetch_chapters(chapter_infos: Vec<ChapterInfo>, content_selectors: &[String],
+observer: &dyn ProgressObserver) -> NovelResult< Vec<Chapter> > {
     let fetch_results: Vec<_> = chapter_infos.into_par_iter()
-        .map(|chapter| chapter.fetch_chapter())
+        .map(|chapter| chapter.fetch_chapter(content_selectors, observer))
         .collect();
     let mut chapters = Vec::new();
     for fetch_result in fetch_results {
@@ -453,3 +726,117 @@ fn fetch_chapters(chapter_infos: Vec<ChapterInfo>) -> NovelResult< Vec<Chapter>
 //     Selectors::compile(selector)
 //         .map_err(|_| NovelError::BadCssSelector(selector.to_string()))
 // }
+
+#[cfg(test)]
+mod tests {
+    use kuchiki::traits::*;
+
+    use super::*;
+
+    // Trimmed down from a work page, with one scheduled episode at the end
+    const MAIN_PAGE_FIXTURE: &'static str = r#"<html><body>
+<h1 id="workTitle"><a href="/works/1">魔法使いの旅</a></h1>
+<span id="workAuthor-activityName"><a href="/users/author">作者</a></span>
+<p id="introduction">
+  旅に出る話です。
+</p>
+<div id="workInformationList"><dl>
+<dt>状態</dt><dd>連載中</dd>
+<dt>エピソード</dt><dd>3話</dd>
+</dl></div>
+<ol>
+<li class="widget-toc-chapter"><span>第一章</span><p>始まりの章</p></li>
+<li class="widget-toc-episode"><a href="/works/1/episodes/1">
+  <span class="widget-toc-episode-titleLabel">第1話</span>
+  <time class="widget-toc-episode-datePublished">2019年1月2日</time>
+</a><span class="widget-toc-episode-label">設定</span></li>
+<li class="widget-toc-episode"><a href="/works/1/episodes/2">
+  <span class="widget-toc-episode-titleLabel">第2話</span>
+  <time class="widget-toc-episode-datePublished">2019年1月3日</time>
+</a></li>
+<li class="widget-toc-episode scheduled"><a href="/works/1/episodes/3">
+  <span class="widget-toc-episode-titleLabel">第3話</span>
+</a></li>
+</ol>
+</body></html>"#;
+
+    fn parse_fixture() -> MainPageData {
+        parse_main_page(kuchiki::parse_html().one(MAIN_PAGE_FIXTURE),
+            &SelectorOverrides::default()).expect("The work page couldn't be parsed")
+    }
+
+    #[test]
+    fn the_work_page_is_parsed_without_fetching() {
+        let main_page_data = parse_fixture();
+        assert_eq!(main_page_data.title.as_ref().map(String::as_str), Some("魔法使いの旅"));
+        assert_eq!(main_page_data.author.as_ref().map(String::as_str), Some("作者"));
+        assert_eq!(main_page_data.synopsis.as_ref().map(String::as_str), Some("旅に出る話です。"));
+        assert_eq!(main_page_data.status, Some(NovelStatus::Running));
+        assert_eq!(main_page_data.stated_chapter_count, Some(3));
+    }
+    #[test]
+    fn the_episodes_end_up_in_their_section() {
+        let main_page_data = parse_fixture();
+        assert!(main_page_data.chapters.is_empty());
+        assert_eq!(main_page_data.sections.len(), 1);
+        let section = &main_page_data.sections[0];
+        assert_eq!(section.name, "第一章");
+        assert_eq!(section.description.as_ref().map(String::as_str), Some("始まりの章"));
+
+        let chapters = &section.chapters;
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].name, "第1話");
+        assert_eq!(chapters[0].date, "二〇一九年一月二日");
+        assert_eq!(chapters[0].order_num, 1);
+        assert_eq!(chapters[0].tags, vec!["設定".to_string()]);
+        assert_eq!(chapters[0].uri_path, "/works/1/episodes/1");
+        assert_eq!(chapters[1].name, "第2話");
+        assert_eq!(chapters[1].tags, Vec::<String>::new());
+    }
+    #[test]
+    fn scheduled_episodes_are_left_out() {
+        let main_page_data = parse_fixture();
+        assert_eq!(main_page_data.unpublished_count, 1);
+    }
+
+    fn parse_episodes(toc_html: &str) -> MainPageData {
+        parse_main_page(kuchiki::parse_html().one(toc_html), &SelectorOverrides::default())
+            .expect("The work page couldn't be parsed")
+    }
+    #[test]
+    fn only_whole_classes_mark_an_episode_as_unpublished() {
+        let main_page_data = parse_episodes(r#"<ol>
+<li class="widget-toc-episode widget-toc-episode-scheduledLabel"><a href="/works/1/episodes/1">
+  <span class="widget-toc-episode-titleLabel">第1話</span>
+  <time class="widget-toc-episode-datePublished">2019年1月2日</time>
+</a></li>
+<li class="widget-toc-episode"><a class="reservation" href="/works/1/episodes/2">
+  <span class="widget-toc-episode-titleLabel">第2話</span>
+</a></li>
+<li class="widget-toc-episode"><a href="/works/1/episodes/3">
+  <span class="widget-toc-episode-titleLabel">第3話</span>
+  <time class="widget-toc-episode-datePublished">2019年1月4日</time>
+</a></li>
+</ol>"#);
+        assert_eq!(main_page_data.unpublished_count, 1);
+        let names: Vec<&str> = main_page_data.chapters.iter()
+            .map(|chapter| chapter.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["第1話", "第3話"]);
+        // The skipped episode doesn't take up a number
+        assert_eq!(main_page_data.chapters[1].order_num, 2);
+    }
+    #[test]
+    fn episodes_without_a_date_are_kept() {
+        let main_page_data = parse_episodes(r#"<ol>
+<li class="widget-toc-episode"><a href="/works/1/episodes/1">
+  <span class="widget-toc-episode-titleLabel">第1話</span>
+</a></li>
+</ol>"#);
+        assert_eq!(main_page_data.unpublished_count, 0);
+        assert_eq!(main_page_data.chapters.len(), 1);
+        assert_eq!(main_page_data.chapters[0].name, "第1話");
+        assert_eq!(main_page_data.chapters[0].date, "");
+        assert_eq!(main_page_data.chapters[0].order_num, 1);
+    }
+}
@@ -1,52 +1,98 @@
 use isahc::http::{Uri};
-use kuchiki::{NodeData, NodeDataRef, ElementData};
+use kuchiki::{NodeDataRef, NodeRef, ElementData};
 
 use crate::{
     NovelError, NovelResult, NovelComponent,
     novel::{
-        Content, ContentLine,
-        novel_utils,
+        ContentLine,
+        novel_utils::{self, ContentData, ParagraphDepth},
     },
     traverser::{TreeTraverser},
 };
 
-const CONTENT_LINE_SELECTOR: &'static str = ".widget-episodeBody > p";
-const BLANK_LINE_NEG: &'static str = ".widget-episodeBody > p.blank";
-const BLANK_LINE_SELECTOR: &'static str = ".widget-episodeBody > p.blank > br";
+pub const CONTENT_SELECTORS: [&'static str; 1] = [".widget-episodeBody"];
 
-pub fn fetch_novel_content(uri: Uri) -> NovelResult< Vec<ContentLine> > {
+pub fn fetch_novel_content(uri: Uri, content_selectors: &[String])
+-> NovelResult< Vec<ContentLine> > {
     let node = crate::fetch_page(&uri)?;
-    let content_data = TreeTraverser::new(node, ContentData::default())
-        .add_hook(CONTENT_LINE_SELECTOR, Some(BLANK_LINE_NEG), ContentData::get_content_line)?
-        .add_hook(BLANK_LINE_SELECTOR, None, ContentData::get_blank_line)?
-        .traverse();
-    if content_data.lines.is_empty() {
+    let (mut lines, matched_selector) = novel_utils::parse_any_content(&node, content_selectors,
+        parse_content)?;
+    let (content_selector, depth) = novel_utils::log_content_selector(&uri, content_selectors,
+        matched_selector);
+    if lines.is_empty() {
+        lines = novel_utils::fetch_embedded_content(&node, content_selector, depth, &uri,
+            parse_content)?;
+    }
+    if lines.is_empty() {
         println!("Couldn't get contents of {:?}", &uri);
         return Err(NovelError::ComponentMissing(NovelComponent::ChapterContent));
     }
+    Ok(lines)
+}
+pub fn parse_content(node: NodeRef, content_selector: &str, depth: ParagraphDepth)
+-> NovelResult< Vec<ContentLine> > {
+    let content_line_selector = depth.inside(content_selector, "p");
+    let blank_line_selector = depth.inside(content_selector, "p.blank > br");
+    let nested_selector = depth.nested_selector(content_selector);
+    let blank_line_neg = match nested_selector.as_ref() {
+        Some(nested_selector) => format!("{}, {}", depth.inside(content_selector, "p.blank"),
+            nested_selector),
+        None => depth.inside(content_selector, "p.blank"),
+    };
+    let nested_neg = nested_selector.as_ref().map(|nested_selector| nested_selector.as_str());
+
+    let traverser = TreeTraverser::new(node, ContentData::default())
+        .add_hook(&content_line_selector, Some(blank_line_neg.as_str()), get_content_line)?
+        .add_hook(&blank_line_selector, nested_neg, get_blank_line)?;
+    let content_data = novel_utils::add_block_hooks(traverser, content_selector, depth,
+        nested_neg)?.traverse();
     Ok(content_data.lines)
 }
 
-#[derive(Debug, Default)]
-struct ContentData {
-    lines: Vec<ContentLine>,
+fn get_content_line(content_data: &mut ContentData, element: &NodeDataRef<ElementData>) {
+    let contents = novel_utils::get_line_contents(element.as_node());
+    let align = novel_utils::get_line_align(element);
+    content_data.lines.push(ContentLine::Line(contents, align));
+}
+fn get_blank_line(content_data: &mut ContentData, _element: &NodeDataRef<ElementData>) {
+    content_data.lines.push(ContentLine::Blank);
 }
-impl ContentData {
-    fn get_content_line(&mut self, element: &NodeDataRef<ElementData>) {
-        let mut contents = Vec::new();
-        for p_child in element.as_node().children() {
-            match p_child.data() {
-                NodeData::Text(text) => contents.push(Content::Span(text.borrow().clone())),
-                NodeData::Element(child_element) => {
-                    let mut ruby_contents = novel_utils::get_ruby(&p_child, &child_element);
-                    contents.append(&mut ruby_contents);
-                },
-                _ => (),
-            }
-        }
-        self.lines.push(ContentLine::Line(contents));
+
+#[cfg(test)]
+mod tests {
+    use kuchiki::traits::*;
+
+    use crate::novel::{Content, LineAlign};
+    use super::*;
+
+    #[test]
+    fn the_episode_is_parsed_without_fetching() {
+        let page_node = kuchiki::parse_html().one(r#"<div class="widget-episodeBody">
+<p>一行目</p>
+<p class="blank"><br></p>
+<p style="text-align: center;">＊＊＊</p>
+<ul><li>魔力：１０</li></ul>
+</div>"#);
+        let lines = parse_content(page_node, CONTENT_SELECTORS[0], ParagraphDepth::Child)
+            .expect("The episode couldn't be parsed");
+        assert_eq!(lines, vec![
+            ContentLine::Line(vec![Content::Span("一行目".to_string())], LineAlign::Start),
+            ContentLine::Blank,
+            ContentLine::Line(vec![Content::Span("＊＊＊".to_string())], LineAlign::Center),
+            ContentLine::List {
+                ordered: false,
+                items: vec![vec![Content::Span("魔力：１０".to_string())]],
+            },
+        ]);
     }
-    fn get_blank_line(&mut self, _element: &NodeDataRef<ElementData>) {
-        self.lines.push(ContentLine::Blank);
+    #[test]
+    fn lines_outside_of_the_episode_are_left_out() {
+        let page_node = kuchiki::parse_html().one(
+            "<p>広告</p><div class=\"widget-episodeBody\"><p>本文</p></div>");
+        let lines = parse_content(page_node, CONTENT_SELECTORS[0], ParagraphDepth::Child)
+            .expect("The episode couldn't be parsed");
+        assert_eq!(lines, vec![
+            ContentLine::Line(vec![Content::Span("本文".to_string())], LineAlign::Start),
+        ]);
     }
 }
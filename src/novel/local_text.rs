@@ -0,0 +1,170 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    NovelInfo, NovelResult,
+    novel::{
+        self, AuthorNotes, Chapter, Content, ContentLine, LineAlign, Novel, NovelStatus,
+        Section,
+        novel_utils,
+    },
+};
+
+const CHAPTER_EXTENSIONS: [&'static str; 2] = ["txt", "md"];
+
+// Each file is a chapter, and each folder (if there are any) is a section
+//  Everything gets sorted by its name, so numbering them is enough to keep them in order
+pub fn make_local_novel(local_dir: &Path, novel_info: &NovelInfo) -> NovelResult<Novel> {
+    let mut order_num = 0;
+    let mut sections = Vec::new();
+    for section_dir in sorted_entries(local_dir, Path::is_dir)? {
+        let chapters = find_chapters(&section_dir, &mut order_num)?;
+        sections.push(LocalSection {
+            name: name_from_path(&section_dir),
+            chapters,
+        });
+    }
+    let chapters = find_chapters(local_dir, &mut order_num)?;
+    let contents = novel::assemble_contents(sections, chapters, novel_info.drop_empty_sections,
+        |section| section.chapters.is_empty(),
+        |sections| sections.into_iter().map(LocalSection::read).collect(),
+        |chapters| chapters.into_iter().map(LocalChapter::read).collect(),
+    )?;
+
+    let mut title = name_from_path(local_dir);
+    let mut author = "不明".to_string();
+    novel::override_metadata(&mut title, &mut author, novel_info);
+    Ok(Novel {
+        title,
+        title_ruby: None,
+        author,
+        // There's nothing to say that it's finished
+        status: NovelStatus::Running,
+        ratings: Vec::new(),
+        keywords: Vec::new(),
+        source_url: local_dir.display().to_string(),
+        chapter_counter: novel_info.chapter_counter.clone().unwrap_or_else(|| "話".to_string()),
+        reading_dir: novel_info.reading_dir,
+        contents,
+    })
+}
+
+fn sorted_entries(dir: &Path, keep: impl Fn(&Path) -> bool) -> NovelResult< Vec<PathBuf> > {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if keep(&path) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+fn find_chapters(dir: &Path, order_num: &mut u32) -> NovelResult< Vec<LocalChapter> > {
+    let is_chapter_file = |path: &Path| path.is_file() && path.extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| CHAPTER_EXTENSIONS.contains(&extension));
+    Ok(sorted_entries(dir, is_chapter_file)?.into_iter()
+        .map(|path| {
+            *order_num += 1;
+            LocalChapter {
+                path,
+                order_num: *order_num,
+            }
+        })
+        .collect())
+}
+// The numbers at the start are only there for the ordering, like 001_プロローグ.txt
+fn name_from_path(path: &Path) -> String {
+    let stem = path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = stem.trim_start_matches(|c: char| {
+        c.is_ascii_digit() || c == ' ' || c == '_' || c == '-' || c == '.'
+    });
+    if name.is_empty() {
+        stem.clone()
+    } else {
+        name.to_string()
+    }
+}
+
+struct LocalSection {
+    name: String,
+    chapters: Vec<LocalChapter>,
+}
+impl LocalSection {
+    fn read(self) -> NovelResult<Section> {
+        let chapters = self.chapters.into_iter()
+            .map(LocalChapter::read)
+            .collect::< NovelResult<_> >()?;
+        Ok(Section {
+            name: self.name,
+            description: None,
+            chapters,
+        })
+    }
+}
+struct LocalChapter {
+    path: PathBuf,
+    order_num: u32,
+}
+impl LocalChapter {
+    fn read(self) -> NovelResult<Chapter> {
+        let file_text = fs::read_to_string(&self.path)?;
+        let mut lines = file_text.trim_start_matches('\u{FEFF}').lines().peekable();
+        // A Markdown heading at the very top is the name of the chapter
+        let first_line = lines.peek().copied();
+        let name = match first_line.and_then(|line| line.strip_prefix("# ")) {
+            Some(heading) => {
+                lines.next();
+                heading.trim().to_string()
+            },
+            None => name_from_path(&self.path),
+        };
+        let content = lines
+            .map(|line| if line.trim().is_empty() {
+                ContentLine::Blank
+            } else {
+                ContentLine::Line(parse_line(line), LineAlign::Start)
+            })
+            .collect();
+        Ok(Chapter {
+            name,
+            date: String::new(),
+            order_num: self.order_num,
+            source_url: self.path.display().to_string(),
+            tags: Vec::new(),
+            content,
+            author_notes: AuthorNotes::default(),
+        })
+    }
+}
+
+// 《》 has the reading for the kanji just before it, or for everything after a ｜
+fn parse_line(line: &str) -> Vec<Content> {
+    let mut contents = Vec::new();
+    let mut rest = line;
+    while let Some(open) = rest.find('《') {
+        novel_utils::push_text(&mut contents, &rest[..open]);
+        let after_open = &rest[open + '《'.len_utf8()..];
+        match after_open.find('》') {
+            Some(close) => {
+                let above = after_open[..close].to_string();
+                // Without anything before it, it can only be a normal pair of brackets
+                if let Err(above) = novel_utils::push_split_ruby(&mut contents, above) {
+                    novel_utils::push_text(&mut contents, &format!("《{}》", above));
+                }
+                rest = &after_open[close + '》'.len_utf8()..];
+            },
+            None => {
+                rest = &rest[open..];
+                break;
+            },
+        }
+    }
+    novel_utils::push_text(&mut contents, rest);
+    contents
+}
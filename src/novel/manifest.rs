@@ -0,0 +1,116 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    NovelResult,
+    novel::{Chapter, FileNames, Novel},
+};
+
+// Everything needed to tell what went into a book without opening it
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BookManifest {
+    pub title: String,
+    pub author: String,
+    pub source_url: String,
+    // Only when the novel was split up into volumes. Older manifests don't have it
+    #[serde(default)]
+    pub volume_num: Option<usize>,
+    pub chapters: Vec<ChapterManifest>,
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChapterManifest {
+    pub order_num: u32,
+    pub name: String,
+    pub date: String,
+    pub source_url: String,
+}
+
+// Goes after the name of the book it's for
+pub const MANIFEST_EXTENSION: &'static str = "manifest.json";
+pub const CHAPTER_INDEX_EXTENSION: &'static str = "index.json";
+
+// Where each chapter is inside of a book, so that a reader can link right to it
+#[derive(Debug, Serialize)]
+struct ChapterIndex {
+    title: String,
+    author: String,
+    chapters: Vec<ChapterIndexEntry>,
+}
+#[derive(Debug, Serialize)]
+struct ChapterIndexEntry {
+    order_num: u32,
+    // The name of the chapter's page, the same as it was added to the book
+    file_name: String,
+    name: String,
+}
+
+pub fn write_manifest(novel: &Novel, chapters: &[&Chapter], volume_num: Option<usize>,
+manifest_path: &Path) -> NovelResult<()> {
+    let manifest = BookManifest {
+        title: novel.title.clone(),
+        author: novel.author.clone(),
+        source_url: novel.source_url.clone(),
+        volume_num,
+        chapters: chapters.iter()
+            .map(|chapter| ChapterManifest {
+                order_num: chapter.order_num,
+                name: chapter.name.clone(),
+                date: chapter.date.clone(),
+                source_url: chapter.source_url.clone(),
+            })
+            .collect(),
+    };
+    fs::write(manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+    Ok(())
+}
+
+pub fn write_chapter_index(novel: &Novel, chapters: &[&Chapter], file_names: &FileNames,
+index_path: &Path) -> NovelResult<()> {
+    let chapter_index = ChapterIndex {
+        title: novel.title.clone(),
+        author: novel.author.clone(),
+        chapters: chapters.iter()
+            .map(|chapter| ChapterIndexEntry {
+                order_num: chapter.order_num,
+                file_name: file_names.chapter(chapter.order_num),
+                name: chapter.name.clone(),
+            })
+            .collect(),
+    };
+    fs::write(index_path, serde_json::to_string_pretty(&chapter_index)?)?;
+    Ok(())
+}
+
+// Every book of a novel has its own manifest, so they all need to be put back together
+//  Folders that aren't there (like a status that no novel has yet) get skipped
+pub fn load_chapter_manifests(book_dirs: &[PathBuf], source_url: &str)
+-> NovelResult< Vec<ChapterManifest> > {
+    let manifest_suffix = format!(".{}", MANIFEST_EXTENSION);
+    let mut chapters = Vec::new();
+    let existing_dirs = book_dirs.iter().filter(|book_dir| book_dir.is_dir());
+    let entries = existing_dirs
+        .map(fs::read_dir)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten();
+    for entry in entries {
+        let path = entry?.path();
+        let is_manifest = path.file_name()
+            .and_then(|file_name| file_name.to_str())
+            .map_or(false, |file_name| file_name.ends_with(&manifest_suffix));
+        if !is_manifest {
+            continue;
+        }
+        let manifest: BookManifest = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        if manifest.source_url == source_url {
+            chapters.extend(manifest.chapters);
+        }
+    }
+    // Books from older builds can still be around with some of the same chapters
+    chapters.sort_by_key(|chapter| chapter.order_num);
+    chapters.dedup_by_key(|chapter| chapter.order_num);
+    Ok(chapters)
+}
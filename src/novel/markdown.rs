@@ -0,0 +1,159 @@
+use std::{
+    fs,
+    path::{Path},
+};
+
+use crate::{
+    NovelResult,
+    novel::{Chapter, Content, ContentLine},
+};
+
+// Anything that Markdown (or the ruby format) would take as formatting, wherever it is in a line
+const MARKDOWN_CHARS: [char; 14] = [
+    '\\', '`', '*', '_', '[', ']', '<', '>', '#', '|', '{', '}', '~', '!',
+];
+
+// Every chapter gets its own file in the folder, padded so that they sort in order
+pub fn write_markdown(chapters: &[&Chapter], markdown_dir: &Path) -> NovelResult<()> {
+    fs::create_dir_all(markdown_dir)?;
+    let max_order_num = chapters.iter().map(|chapter| chapter.order_num).max().unwrap_or(0);
+    let order_num_digits = max_order_num.to_string().len();
+    for chapter in chapters.iter() {
+        let file_name = format!("chapter-{:0width$}.md", chapter.order_num,
+            width = order_num_digits);
+        fs::write(markdown_dir.join(file_name), make_markdown(chapter))?;
+    }
+    Ok(())
+}
+
+fn make_markdown(chapter: &Chapter) -> String {
+    let mut markdown = format!("# {}\n\n> {}\n\n", markdown_text(&chapter.name),
+        markdown_text(&chapter.date));
+    for content_line in chapter.content.iter() {
+        match content_line {
+            ContentLine::Line(contents, _) => {
+                push_contents(&mut markdown, contents);
+                // The 2 spaces make a line break, so each line doesn't get joined to the next one
+                markdown.push_str("  \n");
+            },
+            ContentLine::Blank => markdown.push('\n'),
+            ContentLine::List { ordered, items } => {
+                // A list needs blank lines around it, or it joins the lines next to it
+                markdown.push('\n');
+                for (i, item) in items.iter().enumerate() {
+                    if *ordered {
+                        markdown.push_str(&format!("{}. ", i + 1));
+                    } else {
+                        markdown.push_str("- ");
+                    }
+                    push_contents(&mut markdown, item);
+                    markdown.push('\n');
+                }
+                markdown.push('\n');
+            },
+            ContentLine::Table(rows) => {
+                markdown.push('\n');
+                let column_count = rows.iter().map(|row| row.cells.len()).max().unwrap_or(0);
+                // Markdown only has the one header row, so the first row always takes its place
+                for (i, row) in rows.iter().enumerate() {
+                    markdown.push('|');
+                    for column in 0..column_count {
+                        let mut cell_text = String::new();
+                        if let Some(cell) = row.cells.get(column) {
+                            push_contents(&mut cell_text, cell);
+                        }
+                        markdown.push_str(&format!(" {} |", cell_text));
+                    }
+                    markdown.push('\n');
+                    if i == 0 {
+                        markdown.push('|');
+                        markdown.push_str(&" --- |".repeat(column_count));
+                        markdown.push('\n');
+                    }
+                }
+                markdown.push('\n');
+            },
+        }
+    }
+    markdown
+}
+fn push_contents(markdown: &mut String, contents: &[Content]) {
+    let mut line = String::new();
+    for content in contents.iter() {
+        match content {
+            Content::Span(text) => line.push_str(&markdown_text(text)),
+            // The same ruby format that DenDenMarkdown uses
+            Content::Ruby { main, above } => line.push_str(&format!("{{{}|{}}}",
+                markdown_text(main), markdown_text(above))),
+        }
+    }
+    markdown.push_str(&escape_line_start(line));
+}
+// The text comes back out exactly as it was, instead of turning into formatting
+fn markdown_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if MARKDOWN_CHARS.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+// Some characters only mean something at the start of a line, like - for a list or 1. for a
+//  numbered one. Escaping them everywhere would fill the text with backslashes
+fn escape_line_start(line: String) -> String {
+    let digit_count = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    let (digits, rest) = line.split_at(digit_count);
+    if digit_count > 0 && (rest.starts_with('.') || rest.starts_with(')')) {
+        return format!("{}\\{}", digits, rest);
+    }
+    match line.chars().next() {
+        Some('-') | Some('+') | Some('=') => format!("\\{}", line),
+        _ => line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::novel::{AuthorNotes, LineAlign};
+    use super::*;
+
+    fn line(text: &str) -> ContentLine {
+        ContentLine::Line(vec![Content::Span(text.to_string())], LineAlign::Start)
+    }
+
+    #[test]
+    fn markdown_characters_in_the_text_are_escaped() {
+        let chapter = Chapter {
+            name: "#1 *強い* [勇者]".to_string(),
+            date: "2019年1月1日".to_string(),
+            order_num: 1,
+            source_url: String::new(),
+            tags: Vec::new(),
+            content: vec![
+                line("# 見出しじゃない"),
+                line("_斜体_ と `コード` と <b>タグ</b>"),
+                line("- 箇条書きじゃない"),
+                line("1. 番号付きじゃない"),
+                line("途中の - と 2. はそのまま"),
+                ContentLine::Line(vec![Content::Ruby {
+                    main: "魔法|".to_string(),
+                    above: "まほう}".to_string(),
+                }], LineAlign::Start),
+            ],
+            author_notes: AuthorNotes::default(),
+        };
+        let markdown = make_markdown(&chapter);
+        let lines: Vec<&str> = markdown.lines().collect();
+        assert_eq!(lines[0], "# \\#1 \\*強い\\* \\[勇者\\]");
+        assert_eq!(&lines[4..], &[
+            "\\# 見出しじゃない  ",
+            "\\_斜体\\_ と \\`コード\\` と \\<b\\>タグ\\</b\\>  ",
+            "\\- 箇条書きじゃない  ",
+            "1\\. 番号付きじゃない  ",
+            "途中の - と 2. はそのまま  ",
+            "{魔法\\||まほう\\}}  ",
+        ]);
+    }
+}
@@ -1,18 +1,322 @@
-use kuchiki::{ElementData, NodeData, NodeRef};
+use std::{
+    time::{SystemTime, UNIX_EPOCH},
+};
+use isahc::http::{Uri};
+use kuchiki::{ElementData, NodeData, NodeDataRef, NodeRef};
 
 use crate::{
-    novel::{Content},
+    NovelComponent, NovelError, NovelResult,
+    novel::{Content, ContentLine, LineAlign, TableRow},
+    traverser::{TreeTraverser},
 };
 
-pub fn get_ruby(node: &NodeRef, element_data: &ElementData) -> Vec<Content> {
-    let mut ruby_contents = Vec::new();
+// Member-only novels show a login form instead of the main page
+//  Some sites have a login form on every page, so it only counts when the title is missing too
+pub fn check_login_wall(page_node: &NodeRef, uri: &Uri, title_selector: &str) -> NovelResult<()> {
+    let has_login_form = page_node.select_first("form input[type=\"password\"]").is_ok();
+    let has_title = page_node.select_first(title_selector).is_ok();
+    if has_login_form && !has_title {
+        Err(crate::fetcher::login_required(uri))
+    } else {
+        Ok(())
+    }
+}
+
+// Where the lines (and lists and tables) can be under the content element
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ParagraphDepth {
+    // Like #novel_honbun > p, which every site has used so far
+    Child,
+    // For layouts that wrap the lines in another container
+    Descendant,
+}
+impl ParagraphDepth {
+    // Every content selector gets tried as a child first, so the deeper lines are only a fallback
+    pub const ALL: [ParagraphDepth; 2] = [ParagraphDepth::Child, ParagraphDepth::Descendant];
+
+    // Goes between the content selector and whatever is inside of it
+    fn combinator(self) -> &'static str {
+        match self {
+            Self::Child => " > ",
+            Self::Descendant => " ",
+        }
+    }
+    // Like "#novel_honbun > p", for every selector in the content selector's list
+    pub fn inside(self, content_selector: &str, inner_selector: &str) -> String {
+        selector_inside(content_selector, self.combinator(), inner_selector)
+    }
+    // Anything in a list or a table already goes in with it, so it can't be picked up again
+    //  A child of the content can never be inside of one
+    pub fn nested_selector(self, content_selector: &str) -> Option<String> {
+        match self {
+            Self::Child => None,
+            Self::Descendant => Some(selector_inside(content_selector, " ", "li *, td *, th *")),
+        }
+    }
+}
+
+// Puts every inner selector inside of every outer one, since an override can be a list too
+//  "#a, .b" with "p" becomes "#a > p, .b > p", where only .b would get the p otherwise
+pub fn selector_inside(outer_selector: &str, combinator: &str, inner_selector: &str) -> String {
+    let inner_parts = split_selector_list(inner_selector);
+    split_selector_list(outer_selector).iter()
+        .flat_map(|outer_part| inner_parts.iter()
+            .map(move |inner_part| format!("{}{}{}", outer_part, combinator, inner_part)))
+        .collect::< Vec<_> >()
+        .join(", ")
+}
+// Only the commas between selectors count, not ones in something like :not(.a, .b) or [title="a,b"]
+fn split_selector_list(selector: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut nesting = 0;
+    let mut quote = None;
+    let mut part_start = 0;
+    for (i, c) in selector.char_indices() {
+        match (quote, c) {
+            (Some(quote_char), c) if c == quote_char => quote = None,
+            (Some(_), _) => (),
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '(') | (None, '[') => nesting += 1,
+            (None, ')') | (None, ']') => nesting -= 1,
+            (None, ',') if nesting == 0 => {
+                parts.push(selector[part_start..i].trim());
+                part_start = i + 1;
+            },
+            _ => (),
+        }
+    }
+    parts.push(selector[part_start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+// Only a fallback gets logged, since that means the site has changed its layout for this page
+//  Gives back the selector and depth to keep using for the page (like for an embedded document)
+pub fn log_content_selector<'a>(uri: &Uri, content_selectors: &'a [String],
+matched_selector: Option<(&'a str, ParagraphDepth)>) -> (&'a str, ParagraphDepth) {
+    let main_selector = content_selectors.first().map_or("", |selector| selector.as_str());
+    match matched_selector {
+        Some((matched_selector, depth)) => {
+            if matched_selector != main_selector {
+                println!("Found the contents of {} with the fallback selector {}", uri,
+                    matched_selector);
+            }
+            if depth == ParagraphDepth::Descendant {
+                println!("Found the contents of {} nested deeper inside of {}", uri,
+                    matched_selector);
+            }
+            (matched_selector, depth)
+        },
+        None => (main_selector, ParagraphDepth::Child),
+    }
+}
+
+// Each site finds the lines inside of a content selector its own way
+pub type ContentParser = fn(NodeRef, &str, ParagraphDepth) -> NovelResult< Vec<ContentLine> >;
+// The first selector that finds any lines wins, along with which one it was
+//  Lines nested deeper inside of any of them only get looked for after that
+pub fn parse_any_content<'a>(page_node: &NodeRef, content_selectors: &'a [String],
+parse_content: ContentParser)
+-> NovelResult<(Vec<ContentLine>, Option<(&'a str, ParagraphDepth)>)> {
+    for depth in ParagraphDepth::ALL.iter() {
+        for content_selector in content_selectors.iter() {
+            let lines = parse_content(page_node.clone(), content_selector, *depth)?;
+            if !lines.is_empty() {
+                return Ok((lines, Some((content_selector.as_str(), *depth))));
+            }
+        }
+    }
+    Ok((Vec::new(), None))
+}
+
+// What every site's content parser fills in
+#[derive(Debug, Default)]
+pub struct ContentData {
+    pub lines: Vec<ContentLine>,
+}
+impl ContentData {
+    // Status screens (and other stat blocks) are sometimes lists instead of lines
+    fn get_list(&mut self, element: &NodeDataRef<ElementData>) {
+        self.lines.push(get_list(element));
+    }
+    // Like a skill list or a timeline
+    fn get_table(&mut self, element: &NodeDataRef<ElementData>) {
+        self.lines.extend(get_table(element));
+    }
+}
+// Lists and tables look the same on every site, so only the lines need their own hooks
+pub fn add_block_hooks(traverser: TreeTraverser<ContentData>, content_selector: &str,
+depth: ParagraphDepth, nested_neg: Option<&str>) -> NovelResult< TreeTraverser<ContentData> > {
+    let list_selector = depth.inside(content_selector, "ul, ol");
+    let table_selector = depth.inside(content_selector, "table");
+    Ok(traverser
+        .add_hook(&list_selector, nested_neg, ContentData::get_list)?
+        .add_hook(&table_selector, nested_neg, ContentData::get_table)?)
+}
+
+// Only the text and ruby of a line. Any other tags inside of it get left out
+pub fn get_line_contents(node: &NodeRef) -> Vec<Content> {
+    let mut contents = Vec::new();
+    for child in node.children() {
+        match child.data() {
+            NodeData::Text(text) => push_text(&mut contents, &text.borrow()),
+            NodeData::Element(child_element) => append_ruby(&child, &child_element, &mut contents),
+            _ => (),
+        }
+    }
+    contents
+}
+
+// Works for both <ul> and <ol>. A list inside of an item gets left out like any other tag
+pub fn get_list(element: &NodeDataRef<ElementData>) -> ContentLine {
+    let items = element.as_node().children()
+        .filter(|child| child.as_element()
+            .map_or(false, |child_element| &child_element.name.local == "li"))
+        .map(|item| get_trimmed_contents(&item))
+        .filter(|contents| !contents.is_empty())
+        .collect();
+    ContentLine::List {
+        ordered: &element.name.local == "ol",
+        items,
+    }
+}
+// The whitespace that lays out the HTML ends up around the text of each list item (or cell)
+fn get_trimmed_contents(node: &NodeRef) -> Vec<Content> {
+    let mut contents = get_line_contents(node);
+    if let Some(Content::Span(text)) = contents.first_mut() {
+        *text = text.trim_start().to_string();
+    }
+    if let Some(Content::Span(text)) = contents.last_mut() {
+        *text = text.trim_end().to_string();
+    }
+    contents.retain(|content| match content {
+        Content::Span(text) => !text.is_empty(),
+        Content::Ruby { .. } => true,
+    });
+    contents
+}
+
+// Only when ruby is the only tag right inside of the element, since any others would be left out
+pub fn get_ruby_text(element: &NodeDataRef<ElementData>) -> Option< Vec<Content> > {
+    let child_elements: Vec<NodeRef> = element.as_node().children()
+        .filter(|child| child.as_element().is_some())
+        .collect();
+    let is_all_ruby = child_elements.iter().all(|child| is_element_named(child, "ruby"));
+    if !child_elements.is_empty() && is_all_ruby {
+        Some(get_trimmed_contents(element.as_node()))
+    } else {
+        None
+    }
+}
+// Only the text that the ruby goes over, like for a file name
+pub fn base_text(contents: &[Content]) -> String {
+    contents.iter()
+        .map(|content| match content {
+            Content::Span(text) => text.as_str(),
+            Content::Ruby { main, .. } => main.as_str(),
+        })
+        .collect()
+}
+
+// A table inside of a table (or a cell that spans more than one row or column) can't be shown
+//  as a simple grid, so each row becomes a line with its cells' text instead
+pub fn get_table(element: &NodeDataRef<ElementData>) -> Vec<ContentLine> {
+    let table_node = element.as_node();
+    let rows = get_table_rows(table_node);
+    let is_complex = table_node.descendants().any(|descendant| {
+        descendant.as_element().map_or(false, |descendant_element| {
+            let attributes = descendant_element.attributes.borrow();
+            let spans_more = |span| attributes.get(span)
+                .map_or(false, |span_size| span_size.trim() != "1");
+            &descendant_element.name.local == "table" || spans_more("rowspan")
+                || spans_more("colspan")
+        })
+    });
+    if is_complex {
+        return rows.iter()
+            .map(|(row, _)| {
+                let cell_texts: Vec<String> = get_table_cells(row).iter()
+                    .map(|cell| cell.text_contents().split_whitespace().collect::<Vec<_>>()
+                        .join(" "))
+                    .filter(|cell_text| !cell_text.is_empty())
+                    .collect();
+                cell_texts.join("　")
+            })
+            .filter(|row_text| !row_text.is_empty())
+            .map(|row_text| ContentLine::Line(vec![Content::Span(row_text)], LineAlign::Start))
+            .collect();
+    }
+    let table_rows: Vec<TableRow> = rows.iter()
+        .map(|(row, in_head)| {
+            let cells = get_table_cells(row);
+            let all_th = cells.iter().all(|cell| cell.as_element()
+                .map_or(false, |cell_element| &cell_element.name.local == "th"));
+            TableRow {
+                is_header: *in_head || (!cells.is_empty() && all_th),
+                cells: cells.iter().map(get_trimmed_contents).collect(),
+            }
+        })
+        .filter(|row| row.cells.iter().any(|cell| !cell.is_empty()))
+        .collect();
+    if table_rows.is_empty() {
+        Vec::new()
+    } else {
+        vec![ContentLine::Table(table_rows)]
+    }
+}
+// Every <tr> along with whether it was inside of <thead>. The parser always adds a <tbody>
+fn get_table_rows(table_node: &NodeRef) -> Vec<(NodeRef, bool)> {
+    let mut rows = Vec::new();
+    for child in table_node.children() {
+        let child_name = match child.as_element() {
+            Some(child_element) => child_element.name.local.to_string(),
+            None => continue,
+        };
+        match child_name.as_str() {
+            "tr" => rows.push((child, false)),
+            "thead" | "tbody" | "tfoot" => rows.extend(child.children()
+                .filter(|row| is_element_named(row, "tr"))
+                .map(|row| (row, child_name == "thead"))),
+            _ => (),
+        }
+    }
+    rows
+}
+fn get_table_cells(row: &NodeRef) -> Vec<NodeRef> {
+    row.children()
+        .filter(|cell| is_element_named(cell, "td") || is_element_named(cell, "th"))
+        .collect()
+}
+fn is_element_named(node: &NodeRef, name: &str) -> bool {
+    node.as_element().map_or(false, |element| &element.name.local == name)
+}
+
+// kuchiki can split up the text of a line into many text nodes, which would each become a span
+//  Joining them here means that everything afterwards can treat a line's text as one piece
+pub fn push_text(contents: &mut Vec<Content>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    match contents.last_mut() {
+        Some(Content::Span(span_text)) => span_text.push_str(text),
+        _ => contents.push(Content::Span(text.to_string())),
+    }
+}
+
+// The contents are the line so far, since the ruby might need to take its base from them
+pub fn append_ruby(node: &NodeRef, element_data: &ElementData, contents: &mut Vec<Content>) {
     if &element_data.name.local == "ruby" {
         let mut main: Option<String> = None;
         let mut above: Option<String> = None;
         for ruby_child in node.children() {
             match ruby_child.clone().data() {
                 NodeData::Text(text) => {
-                    main = Some(text.borrow().clone());
+                    let text = text.borrow();
+                    // Whitespace between the tags would otherwise replace the real base
+                    //  (and the whitespace around the base is only there to lay out the HTML)
+                    if !text.trim().is_empty() {
+                        main = Some(text.trim().to_string());
+                    }
                     continue;
                 },
                 NodeData::Element(ruby_child_element) => {
@@ -26,19 +330,285 @@ pub fn get_ruby(node: &NodeRef, element_data: &ElementData) -> Vec<Content> {
             }
 
             if main.is_some() && above.is_some() {
-                ruby_contents.push(Content::Ruby {
+                contents.push(Content::Ruby {
                     main: main.take().unwrap(),
                     above: above.take().unwrap(),
                 });
             }
         }
-        if main.is_some() || above.is_some() {
-            panic!("Bad ruby. Main: {:?}. Above: {:?}", main, above);
+        match (main, above) {
+            // Some pages leave the base as the text just before a ruby that only has the reading
+            //  Without any text there (like at the start of a line), the reading is kept as text
+            (None, Some(above)) => if let Err(above) = push_split_ruby(contents, above) {
+                println!("Keeping the reading {} as text, since it doesn't have a base", &above);
+                push_text(contents, &above);
+            },
+            // A base without its reading is still part of the line
+            (Some(main), None) => push_text(contents, &main),
+            // Every pair was already pushed inside of the loop
+            _ => (),
+        }
+    }
+}
+// The base comes off the end of the text before it. Gives back the reading if there isn't any
+pub fn push_split_ruby(contents: &mut Vec<Content>, above: String) -> Result<(), String> {
+    let preceding_text = match contents.last_mut() {
+        Some(Content::Span(text)) => text,
+        _ => return Err(above),
+    };
+    let main = split_off_ruby_base(preceding_text);
+    let nothing_left = preceding_text.is_empty();
+    if nothing_left {
+        contents.pop();
+    }
+    contents.push(Content::Ruby { main, above });
+    Ok(())
+}
+// Takes the base of a ruby off the end of the text that came before it
+fn split_off_ruby_base(text: &mut String) -> String {
+    const BASE_MARKER: char = '｜';
+    let base_start = match text.rfind(BASE_MARKER) {
+        // The marker says exactly where the base starts
+        Some(marker_start) => marker_start + BASE_MARKER.len_utf8(),
+        // Otherwise the reading is almost always for the kanji right before it
+        None => text.char_indices().rev()
+            .take_while(|&(_, c)| is_kanji(c))
+            .last()
+            .map(|(i, _)| i)
+            // Without any kanji, the best guess is the last character
+            .unwrap_or_else(|| text.char_indices().last().map(|(i, _)| i).unwrap_or(0)),
+    };
+    let base = text.split_off(base_start);
+    if text.ends_with(BASE_MARKER) {
+        text.pop();
+    }
+    base
+}
+fn is_kanji(c: char) -> bool {
+    ('\u{4E00}'..='\u{9FFF}').contains(&c) || ('\u{3400}'..='\u{4DBF}').contains(&c) ||
+        c == '々' || c == '〆' || c == 'ヶ'
+}
+
+// Only whole classes count, so that something like "right-side-note" doesn't move a line
+const CENTER_CLASSES: [&'static str; 5] = [
+    "center", "centered", "align-center", "text-center", "aligncenter",
+];
+const RIGHT_CLASSES: [&'static str; 4] = ["right", "align-right", "text-right", "alignright"];
+// Looks at the style, the old align attribute, then the classes of a line for any alignment
+pub fn get_line_align(element_data: &ElementData) -> LineAlign {
+    let attributes = element_data.attributes.borrow();
+    let style_align = attributes.get("style").and_then(|style| style.split(';')
+        .filter_map(|declaration| {
+            let mut parts = declaration.splitn(2, ':');
+            let property = parts.next()?.trim();
+            // Like "center !important", where only the first word is the alignment
+            let value = parts.next()?.split_whitespace().next()?;
+            if property.eq_ignore_ascii_case("text-align") {
+                Some(value.to_ascii_lowercase())
+            } else {
+                None
+            }
+        })
+        .last()
+    );
+    let align = style_align
+        .or_else(|| attributes.get("align").map(|align| align.trim().to_ascii_lowercase()));
+    match align.as_ref().map(|align| align.as_str()) {
+        Some("center") => return LineAlign::Center,
+        Some("right") | Some("end") => return LineAlign::Right,
+        // Something like left or justify is still set on purpose, so the classes can't change it
+        Some(_) => return LineAlign::Start,
+        None => (),
+    }
+
+    if let Some(classes) = attributes.get("class") {
+        for class in classes.split_whitespace() {
+            let class = class.to_ascii_lowercase();
+            if CENTER_CLASSES.contains(&class.as_str()) {
+                return LineAlign::Center;
+            } else if RIGHT_CLASSES.contains(&class.as_str()) {
+                return LineAlign::Right;
+            }
+        }
+    }
+    LineAlign::Start
+}
+
+// Only the main page, just to make sure that the novel is still there
+pub fn fetch_title(uri: &Uri, title_selectors: &[&str]) -> NovelResult<String> {
+    find_title(&crate::fetch_page(uri)?, title_selectors)
+}
+// Each selector gets tried in order, for sites with a fallback when the usual one isn't there
+pub fn find_title(page_node: &NodeRef, title_selectors: &[&str]) -> NovelResult<String> {
+    let title_element = title_selectors.iter()
+        .find_map(|title_selector| page_node.select_first(title_selector).ok())
+        .ok_or(NovelError::ComponentMissing(NovelComponent::Title))?;
+    Ok(title_element.text_contents().trim().to_string())
+}
+
+// Where a chapter's content is when it isn't right in the page
+pub enum EmbeddedContent {
+    // The real content is in another document that we can fetch
+    Found(Uri),
+    // The place for the content is there, but only JavaScript will fill it in
+    ScriptOnly,
+    // There isn't even a place for the content
+    Missing,
+}
+// Only needed when we couldn't find any content where it should be
+const CONTENT_URL_ATTRIBUTES: [&'static str; 3] = ["data-src", "data-url", "data-content-url"];
+pub fn find_embedded_content(page_node: &NodeRef, content_selector: &str, page_uri: &Uri)
+-> NovelResult<EmbeddedContent> {
+    let content_node = match page_node.select_first(content_selector) {
+        Ok(content_element) => content_element.as_node().clone(),
+        Err(_) => return Ok(EmbeddedContent::Missing),
+    };
+    let iframe_src = content_node.select("iframe").ok()
+        .and_then(|mut iframes| iframes.find_map(|iframe| {
+            let attributes = iframe.attributes.borrow();
+            let src = attributes.get("src").map(|src| src.to_string());
+            src
+        }));
+    // The placeholder itself is just as likely to have the link as anything inside of it
+    let data_url = content_node.inclusive_descendants()
+        .filter_map(|node| node.into_element_ref())
+        .find_map(|element| {
+            let attributes = element.attributes.borrow();
+            let data_url = CONTENT_URL_ATTRIBUTES.iter()
+                .find_map(|attribute| attributes.get(*attribute))
+                .map(|url| url.to_string());
+            data_url
+        });
+    match iframe_src.or(data_url) {
+        Some(link) => Ok(EmbeddedContent::Found(resolve_link(page_uri, &link)?)),
+        None => Ok(EmbeddedContent::ScriptOnly),
+    }
+}
+// Only needed when nothing was found where the contents should be
+pub fn fetch_embedded_content(page_node: &NodeRef, content_selector: &str, depth: ParagraphDepth,
+page_uri: &Uri, parse_content: ContentParser) -> NovelResult< Vec<ContentLine> > {
+    match find_embedded_content(page_node, content_selector, page_uri)? {
+        EmbeddedContent::Found(inner_uri) => {
+            println!("Getting the contents of {} from {}", page_uri, &inner_uri);
+            let inner_node = crate::fetch_page(&inner_uri)?;
+            let lines = parse_content(inner_node.clone(), content_selector, depth)?;
+            // The inner document is usually nothing but the content
+            if lines.is_empty() {
+                return parse_content(inner_node, "body", depth);
+            }
+            Ok(lines)
+        },
+        EmbeddedContent::ScriptOnly => {
+            println!("The contents of {} only get loaded by JavaScript", page_uri);
+            Ok(Vec::new())
+        },
+        EmbeddedContent::Missing => Ok(Vec::new()),
+    }
+}
+// Links can be relative to the page they were on
+pub fn resolve_link(page_uri: &Uri, link: &str) -> NovelResult<Uri> {
+    let scheme = page_uri.scheme_str().unwrap_or("https");
+    let authority = page_uri.authority_part().map(|authority| authority.as_str()).unwrap_or("");
+    let full_link = if link.starts_with("http") {
+        link.to_string()
+    } else if link.starts_with("//") {
+        format!("{}:{}", scheme, link)
+    } else if link.starts_with('/') {
+        format!("{}://{}{}", scheme, authority, link)
+    } else {
+        let page_path = page_uri.path();
+        let page_dir = &page_path[..page_path.rfind('/').map_or(0, |i| i + 1)];
+        format!("{}://{}{}{}", scheme, authority, page_dir, link)
+    };
+    Ok(full_link.parse()?)
+}
+
+// A difference usually means that a page of the table of contents (or a selector) got missed
+pub fn warn_if_chapter_count_differs(stated_count: Option<u32>, parsed_count: usize) {
+    if let Some(stated_count) = stated_count {
+        if stated_count as usize != parsed_count {
+            println!("Warning: The site says there are {} chapters, but we found {}",
+                stated_count, parsed_count);
+        }
+    }
+}
+// Big numbers can have commas in them, like 1,234
+pub fn parse_count(count_text: &str) -> Option<u32> {
+    count_text.chars()
+        .filter(|&c| c != ',')
+        .collect::<String>()
+        .parse()
+        .ok()
+}
+
+// Keeps the chapter usable in the table of contents when the site didn't give it a name
+pub fn name_or_fallback(name: String, order_num: u32, fallback_name: &str) -> String {
+    if !name.trim().is_empty() {
+        return name;
+    }
+    let fallback = fallback_name.replace("{num}", &order_num.to_string());
+    println!("Chapter {} has no name, so it will be called {}", order_num, &fallback);
+    fallback
+}
+
+// Today's date (in UTC) like 2019年11月5日, without pulling in a whole date library
+pub fn today_ja() -> String {
+    let days_since_epoch = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs() / (60 * 60 * 24))
+        .unwrap_or(0) as i64;
+    // Taken from the civil_from_days algorithm by Howard Hinnant
+    //  The years start in March so that the leap day is at the very end
+    let days = days_since_epoch + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524
+        - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let march_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * march_month + 2) / 5 + 1;
+    let month = if march_month < 10 { march_month + 3 } else { march_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{}年{}月{}日", year, month, day)
+}
+
+// Writes the number out the way it would be read, like 百二十三 instead of 一二三
+pub fn convert_num_to_ja(num: u32) -> String {
+    if num == 0 {
+        return "〇".to_string();
+    }
+    const DIGITS: [&'static str; 10] = ["", "一", "二", "三", "四", "五", "六", "七", "八", "九"];
+    const SMALL_UNITS: [&'static str; 4] = ["", "十", "百", "千"];
+    const LARGE_UNITS: [&'static str; 3] = ["", "万", "億"];
+
+    // Every 4 digits gets its own large unit, starting from the smallest
+    let mut groups = Vec::new();
+    let mut rest = num;
+    while rest > 0 {
+        groups.push(rest % 10_000);
+        rest /= 10_000;
+    }
+    let mut ja_num = String::new();
+    for (group_index, &group) in groups.iter().enumerate().rev() {
+        if group == 0 {
+            continue;
+        }
+        for digit_index in (0..4).rev() {
+            let digit = (group / 10u32.pow(digit_index as u32) % 10) as usize;
+            if digit == 0 {
+                continue;
+            }
+            // It's just 十 instead of 一十 (same for 百 and 千)
+            if digit != 1 || digit_index == 0 {
+                ja_num.push_str(DIGITS[digit]);
+            }
+            ja_num.push_str(SMALL_UNITS[digit_index]);
         }
+        ja_num.push_str(LARGE_UNITS[group_index]);
     }
-    ruby_contents
+    ja_num
 }
 
+// Digit by digit, like 一二三. Anything that isn't a digit is kept as it is, so this never fails
 pub fn convert_num_string_to_ja(num_string: &str) -> String {
     num_string.chars().map(|c| match c {
         '0' => '〇',
@@ -54,3 +624,198 @@ pub fn convert_num_string_to_ja(num_string: &str) -> String {
         _ => c,
     }).collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use kuchiki::traits::*;
+
+    use super::*;
+
+    fn span(text: &str) -> Content { Content::Span(text.to_string()) }
+    fn ruby(main: &str, above: &str) -> Content {
+        Content::Ruby { main: main.to_string(), above: above.to_string() }
+    }
+    // The contents of the first <p> in the fixture
+    fn parse_line(html: &str) -> Vec<Content> {
+        let page_node = kuchiki::parse_html().one(html);
+        let line = page_node.select_first("p").expect("The fixture needs a <p>");
+        get_line_contents(line.as_node())
+    }
+    // The parser doesn't always keep a line's text together, so build one that's been split up
+    fn fragmented_line(fragments: &[&str], ruby_html: Option<&str>) -> NodeRef {
+        let line = kuchiki::parse_html().one("<p></p>").select_first("p").unwrap()
+            .as_node().clone();
+        for fragment in fragments {
+            line.append(NodeRef::new_text(*fragment));
+        }
+        if let Some(ruby_html) = ruby_html {
+            let ruby_page = kuchiki::parse_html().one(ruby_html);
+            let ruby_node = ruby_page.select_first("ruby").unwrap().as_node().clone();
+            ruby_node.detach();
+            line.append(ruby_node);
+        }
+        line
+    }
+
+    #[test]
+    fn annotation_only_ruby_takes_its_base_from_the_text_before_it() {
+        let contents = parse_line("<p>彼は魔法<ruby><rt>まほう</rt></ruby>を使った</p>");
+        assert_eq!(contents, vec![span("彼は"), ruby("魔法", "まほう"), span("を使った")]);
+    }
+    #[test]
+    fn annotation_only_ruby_at_the_start_of_a_line_stays_as_text() {
+        let contents = parse_line("<p><ruby><rt>まほう</rt></ruby>を使った</p>");
+        assert_eq!(contents, vec![span("まほうを使った")]);
+    }
+    #[test]
+    fn annotation_only_ruby_after_another_ruby_stays_as_text() {
+        let contents = parse_line(
+            "<p><ruby>魔法<rt>まほう</rt></ruby><ruby><rt>つかい</rt></ruby></p>");
+        assert_eq!(contents, vec![ruby("魔法", "まほう"), span("つかい")]);
+    }
+    #[test]
+    fn ruby_without_a_reading_keeps_its_base() {
+        let contents = parse_line("<p>彼は<ruby>魔法</ruby>を使った</p>");
+        assert_eq!(contents, vec![span("彼は魔法を使った")]);
+    }
+
+    #[test]
+    fn fragmented_text_becomes_one_span() {
+        let line = fragmented_line(&["彼は", "", "魔法を", "使った"], None);
+        assert_eq!(line.children().count(), 4);
+        assert_eq!(get_line_contents(&line), vec![span("彼は魔法を使った")]);
+    }
+    #[test]
+    fn annotation_only_ruby_takes_its_base_across_fragments() {
+        let line = fragmented_line(&["彼は", "魔", "法"], Some("<ruby><rt>まほう</rt></ruby>"));
+        assert_eq!(get_line_contents(&line), vec![span("彼は"), ruby("魔法", "まほう")]);
+    }
+
+    #[test]
+    fn order_numbers_are_converted_digit_by_digit() {
+        assert_eq!(convert_num_string_to_ja("0"), "〇");
+        assert_eq!(convert_num_string_to_ja("1234567890"), "一二三四五六七八九〇");
+        assert_eq!(convert_num_string_to_ja(&u32::max_value().to_string()), "四二九四九六七二九五");
+    }
+    #[test]
+    fn anything_that_is_not_a_digit_is_kept() {
+        assert_eq!(convert_num_string_to_ja(""), "");
+        assert_eq!(convert_num_string_to_ja("第12話"), "第一二話");
+        assert_eq!(convert_num_string_to_ja("-1.5e3"), "-一.五e三");
+        // Full-width digits aren't ASCII digits, so they come back out as they are
+        assert_eq!(convert_num_string_to_ja("１２"), "１２");
+        assert_eq!(convert_num_string_to_ja("2019/01/02 03:04"), "二〇一九/〇一/〇二 〇三:〇四");
+    }
+
+    // The element that holds a title in the fixture
+    fn title_element(html: &str) -> NodeDataRef<ElementData> {
+        kuchiki::parse_html().one(html).select_first(".title").expect("The fixture needs a title")
+    }
+
+    #[test]
+    fn title_with_ruby_keeps_it() {
+        let title = title_element("<h1 class=\"title\">\n  <ruby>魔法<rt>まほう</rt></ruby>使いの\
+            <ruby>旅<rt>たび</rt></ruby>\n</h1>");
+        let title_ruby = get_ruby_text(&title).expect("The title has ruby");
+        assert_eq!(title_ruby, vec![ruby("魔法", "まほう"), span("使いの"), ruby("旅", "たび")]);
+        assert_eq!(base_text(&title_ruby), "魔法使いの旅");
+    }
+    #[test]
+    fn title_without_ruby_has_none() {
+        assert_eq!(get_ruby_text(&title_element("<h1 class=\"title\">魔法使いの旅</h1>")), None);
+    }
+    #[test]
+    fn title_with_other_tags_has_none() {
+        // The link would be left out, so the plain text is used instead
+        let title = title_element(
+            "<h1 class=\"title\"><ruby>魔法<rt>まほう</rt></ruby><a href=\"/\">使い</a></h1>");
+        assert_eq!(get_ruby_text(&title), None);
+    }
+
+    #[test]
+    fn whitespace_after_the_reading_is_skipped() {
+        let contents = parse_line("<p>彼は<ruby>魔法<rt>まほう</rt>\n</ruby>を使った</p>");
+        assert_eq!(contents, vec![span("彼は"), ruby("魔法", "まほう"), span("を使った")]);
+    }
+    #[test]
+    fn whitespace_around_the_base_is_skipped() {
+        let contents = parse_line("<p>彼は<ruby>\n  魔法\n  <rp>（</rp><rt>まほう</rt><rp>）</rp>\n\
+            </ruby>を使った</p>");
+        assert_eq!(contents, vec![span("彼は"), ruby("魔法", "まほう"), span("を使った")]);
+    }
+    #[test]
+    fn whitespace_between_pairs_is_skipped() {
+        let contents = parse_line("<p><ruby>\n魔法<rt>まほう</rt>\n使<rt>つか</rt>\n</ruby>い</p>");
+        assert_eq!(contents, vec![ruby("魔法", "まほう"), ruby("使", "つか"), span("い")]);
+    }
+
+    fn align_of(html: &str) -> LineAlign {
+        let page_node = kuchiki::parse_html().one(html);
+        let line = page_node.select_first("p").expect("The line is missing");
+        get_line_align(&line)
+    }
+    #[test]
+    fn only_whole_classes_align_a_line() {
+        assert_eq!(align_of("<p class=\"line text-center\">一</p>"), LineAlign::Center);
+        assert_eq!(align_of("<p class=\"Right\">一</p>"), LineAlign::Right);
+        assert_eq!(align_of("<p class=\"right-side-note\">一</p>"), LineAlign::Start);
+        assert_eq!(align_of("<p class=\"uncentered\">一</p>"), LineAlign::Start);
+    }
+    #[test]
+    fn the_text_align_value_is_read_from_the_style() {
+        assert_eq!(align_of("<p style=\"color: red; text-align: center !important\">一</p>"),
+            LineAlign::Center);
+        // The property has to be text-align itself, not only end with it
+        assert_eq!(align_of("<p style=\"-x-text-align: right\">一</p>"), LineAlign::Start);
+        assert_eq!(align_of("<p style=\"text-align:end\">一</p>"), LineAlign::Right);
+        assert_eq!(align_of("<p style=\"text-align: left\" class=\"center\">一</p>"),
+            LineAlign::Start);
+    }
+
+    #[test]
+    fn every_selector_in_a_list_gets_the_inner_selector() {
+        assert_eq!(ParagraphDepth::Child.inside("#novel_honbun, .novel_view", "p"),
+            "#novel_honbun > p, .novel_view > p");
+        assert_eq!(ParagraphDepth::Descendant.inside("#a, .b", "ul, ol"),
+            "#a ul, #a ol, .b ul, .b ol");
+    }
+    #[test]
+    fn commas_inside_of_a_selector_do_not_split_it() {
+        assert_eq!(ParagraphDepth::Child.inside(".text:not(.a, .b), [title=\"x,y\"]", "p"),
+            ".text:not(.a, .b) > p, [title=\"x,y\"] > p");
+    }
+    #[test]
+    fn lines_are_found_in_every_part_of_an_override_list() {
+        let page_node = kuchiki::parse_html().one("<div id=\"a\"><ul><li>一</li></ul></div>\
+            <div class=\"b\"><ol><li>二</li></ol></div>");
+        let lines = parse_blocks(page_node, "#a, .b", ParagraphDepth::Child)
+            .expect("The contents couldn't be parsed");
+        assert_eq!(lines.len(), 2, "Lines: {:?}", lines);
+    }
+
+    #[test]
+    fn the_title_comes_from_the_first_selector_that_finds_it() {
+        let page_node = kuchiki::parse_html().one("<h1 id=\"workTitle\"> 魔法使いの旅 </h1>");
+        assert_eq!(find_title(&page_node, &["#workTitle > a", "#workTitle"]).ok(),
+            Some("魔法使いの旅".to_string()));
+        assert!(matches!(find_title(&page_node, &[".novel_title"]),
+            Err(NovelError::ComponentMissing(NovelComponent::Title))));
+    }
+
+    // Only the shared hooks, without any of a site's own line hooks
+    fn parse_blocks(node: NodeRef, content_selector: &str, depth: ParagraphDepth)
+    -> NovelResult< Vec<ContentLine> > {
+        let traverser = TreeTraverser::new(node, ContentData::default());
+        Ok(add_block_hooks(traverser, content_selector, depth, None)?.traverse().lines)
+    }
+    #[test]
+    fn the_fallback_selector_is_used_when_the_main_one_has_nothing() {
+        let page_node = kuchiki::parse_html().one(
+            "<div id=\"main\"></div><div class=\"fallback\"><ol><li> 一 </li></ol></div>");
+        let content_selectors = vec!["#main".to_string(), ".fallback".to_string()];
+        let (lines, matched_selector) = parse_any_content(&page_node, &content_selectors,
+            parse_blocks).expect("The contents couldn't be parsed");
+        assert_eq!(lines, vec![ContentLine::List { ordered: true, items: vec![vec![span("一")]] }]);
+        assert_eq!(matched_selector, Some((".fallback", ParagraphDepth::Child)));
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{
+    NovelResult, OmnibusInfo, RunInfo,
+    novel::{
+        Novel, NovelContents, NovelStatus, ReadingDirection,
+        epub::{NovelBook},
+    },
+};
+
+// Every novel gets its own title page, followed by all of its sections and chapters
+//  Only one book gets made, no matter how the volumes would normally be split up
+pub fn save_omnibus(omnibus_info: &OmnibusInfo, novels: &[Novel], run_info: &RunInfo)
+-> NovelResult<()> {
+    // The whole book can only go one way, so the first novel decides it
+    let reading_dir = novels.first()
+        .map_or(ReadingDirection::default(), |novel| novel.reading_dir);
+    let mut book = NovelBook::new(&omnibus_info.title, reading_dir, run_info.xhtml_format);
+    let mut authors: Vec<&str> = Vec::new();
+    for novel in novels.iter() {
+        if !authors.contains(&novel.author.as_str()) {
+            authors.push(&novel.author);
+            book.add_author(&novel.author);
+        }
+    }
+    super::add_styles(&mut book, run_info, reading_dir)?;
+
+    let mut contents_links = Vec::new();
+    for (i, novel) in novels.iter().enumerate() {
+        // Chapter (and section) numbers start over in every novel, so the files would clash
+        let file_names = novel.file_names(&format!("novel-{}-", i + 1));
+        if i > 0 {
+            super::add_separator(&mut book, run_info, &file_names.separator(0))?;
+        }
+        novel.add_title_page(&mut book, run_info, &file_names, &novel.title)?;
+        match &novel.contents {
+            NovelContents::Sections(sections) => {
+                for (section_index, section) in sections.iter().enumerate() {
+                    if section_index > 0 {
+                        super::add_separator(&mut book, run_info,
+                            &file_names.separator(section_index + 1))?;
+                    }
+                    book = section.fill_out_book(section_index + 1, book, run_info,
+                        &novel.chapter_counter, &file_names)?;
+                }
+            },
+            NovelContents::Chapters(chapters) => {
+                for chapter in chapters.iter() {
+                    chapter.add_to_book(&mut book, run_info, &novel.chapter_counter,
+                        &file_names)?;
+                }
+            },
+        }
+        contents_links.extend(novel.all_chapters().into_iter()
+            .map(|chapter| (file_names.chapter(chapter.order_num), chapter.name.as_str())));
+    }
+    if run_info.chapter_toc_link {
+        super::add_contents_page(&mut book, contents_links.into_iter())?;
+    }
+
+    let kan_stamp = if novels.iter().all(|novel| novel.status == NovelStatus::Finished) {
+        NovelStatus::Finished.kan_stamp(run_info)
+    } else { "" };
+    let name = format!("{} [{}] (投稿版){}", &omnibus_info.title, authors.join("・"), kan_stamp);
+    let book_name = crate::truncate_book_name(&crate::sanitize_book_name(&name),
+        crate::MAX_BOOK_NAME_BYTES);
+    let book_path = run_info.save_dir.join(
+        format!("{}.{}", book_name, run_info.ebook_type.extension()));
+    book.save_to_file(run_info.ebook_type.ebook_type(), &book_path)?;
+    super::print_book_size(&book_name, &book_path);
+    if run_info.verify_links {
+        super::print_broken_links(&book_name, &book_path);
+    }
+    if let Some(command) = run_info.post_build_command.as_ref() {
+        crate::run_post_build_command(command, &book_path);
+    }
+    Ok(())
+}
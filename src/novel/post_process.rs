@@ -0,0 +1,126 @@
+use serde::{Deserialize};
+
+use crate::{
+    RunInfo,
+    novel::{Content, ContentLine, TableRow, novel_utils},
+};
+
+// Changes the lines of every chapter after they've all been fetched, but before any books
+//  Chapters get processed on their own, so nothing can depend on the chapters around it
+pub trait PostProcessor: Send + Sync {
+    fn process(&self, lines: Vec<ContentLine>) -> Vec<ContentLine>;
+}
+
+// The ones that can be set in novel_info.toml, which run in the same order they are listed
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostProcessStep {
+    TrimBlankEdges,
+    KanjiDigits,
+    Replace {
+        from: String,
+        to: String,
+    },
+}
+impl PostProcessStep {
+    fn into_processor(self) -> Box<dyn PostProcessor> {
+        match self {
+            Self::TrimBlankEdges => Box::new(TrimBlankEdges),
+            Self::KanjiDigits => Box::new(KanjiDigits),
+            Self::Replace { from, to } => Box::new(Replace { from, to }),
+        }
+    }
+}
+
+pub struct PostProcessPipeline {
+    processors: Vec< Box<dyn PostProcessor> >,
+}
+impl PostProcessPipeline {
+    pub fn new(run_info: &RunInfo) -> PostProcessPipeline {
+        let mut processors: Vec< Box<dyn PostProcessor> > = Vec::new();
+        // It was an option before the pipeline, so it still goes first
+        if run_info.trim_blank_edges {
+            processors.push(Box::new(TrimBlankEdges));
+        }
+        processors.extend(run_info.post_processors.iter().cloned()
+            .map(PostProcessStep::into_processor));
+        PostProcessPipeline { processors }
+    }
+
+    // Goes after every processor that's already there
+    pub fn push(&mut self, processor: Box<dyn PostProcessor>) {
+        self.processors.push(processor);
+    }
+
+    pub fn is_empty(&self) -> bool { self.processors.is_empty() }
+
+    pub fn run(&self, lines: Vec<ContentLine>) -> Vec<ContentLine> {
+        self.processors.iter().fold(lines, |lines, processor| processor.process(lines))
+    }
+}
+
+// Only the blank lines before the first line and after the last one
+struct TrimBlankEdges;
+impl PostProcessor for TrimBlankEdges {
+    fn process(&self, mut lines: Vec<ContentLine>) -> Vec<ContentLine> {
+        let is_blank = |line: &ContentLine| match line {
+            ContentLine::Blank => true,
+            ContentLine::Line(..) | ContentLine::List { .. } | ContentLine::Table(_) => false,
+        };
+        let end = lines.iter().rposition(|line| !is_blank(line)).map_or(0, |i| i + 1);
+        lines.truncate(end);
+        let start = lines.iter().position(|line| !is_blank(line)).unwrap_or(0);
+        lines.drain(..start);
+        lines
+    }
+}
+
+// 123 becomes 一二三, which reads better when the text is vertical
+struct KanjiDigits;
+impl PostProcessor for KanjiDigits {
+    fn process(&self, lines: Vec<ContentLine>) -> Vec<ContentLine> {
+        map_spans(lines, novel_utils::convert_num_string_to_ja)
+    }
+}
+
+// Like fixing a typo that the author keeps making
+struct Replace {
+    from: String,
+    to: String,
+}
+impl PostProcessor for Replace {
+    fn process(&self, lines: Vec<ContentLine>) -> Vec<ContentLine> {
+        if self.from.is_empty() {
+            return lines;
+        }
+        map_spans(lines, |text| text.replace(&self.from, &self.to))
+    }
+}
+
+// Ruby is left alone, since its base and reading have to stay matched up
+fn map_spans(lines: Vec<ContentLine>, map_text: impl Fn(&str) -> String) -> Vec<ContentLine> {
+    let map_contents = |contents: Vec<Content>| -> Vec<Content> {
+        contents.into_iter()
+            .map(|content| match content {
+                Content::Span(text) => Content::Span(map_text(&text)),
+                ruby => ruby,
+            })
+            .collect()
+    };
+    lines.into_iter()
+        .map(|line| match line {
+            ContentLine::Line(contents, align) => ContentLine::Line(map_contents(contents), align),
+            ContentLine::List { ordered, items } => ContentLine::List {
+                ordered,
+                items: items.into_iter().map(&map_contents).collect(),
+            },
+            ContentLine::Table(rows) => ContentLine::Table(rows.into_iter()
+                .map(|row| TableRow {
+                    is_header: row.is_header,
+                    cells: row.cells.into_iter().map(&map_contents).collect(),
+                })
+                .collect()),
+            ContentLine::Blank => ContentLine::Blank,
+        })
+        .collect()
+}
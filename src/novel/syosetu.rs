@@ -1,12 +1,19 @@
-mod content;
+pub mod content;
 mod info_page;
 
+use std::{
+    mem,
+};
 use isahc::http::{Uri};
-use kuchiki::{ElementData, NodeDataRef};
+use kuchiki::{ElementData, NodeDataRef, NodeRef};
 
 use crate::{
-    NovelComponent, NovelError, NovelResult,
-    novel::{Novel, Section, Chapter, NovelContents},
+    NovelComponent, NovelError, NovelInfo, NovelResult,
+    novel::{
+        self, Content, Novel, NovelOverview, Section, Chapter, SelectorOverrides, novel_utils,
+        manifest::{ChapterManifest},
+    },
+    progress::{ProgressObserver},
     traverser::{TreeTraverser},
 };
 
@@ -33,63 +40,152 @@ pub fn is_syosetu_novel(uri: &Uri) -> bool {
 
 const TITLE_SELECTOR: &'static str = ".novel_title";
 const AUTHOR_SELECTOR: &'static str = "div.novel_writername > a";
+// Authors without a user page (or a cached page) only have the text, right after 作者：
+const AUTHOR_FALLBACK_SELECTOR: &'static str = "div.novel_writername";
+// Only on the first page of the table of contents
+const SYNOPSIS_SELECTOR: &'static str = "#novel_ex";
 const INFO_LINK_SELECTOR: &'static str = "#head_nav > li:nth-child(2) > a";
 const SECTION_SELECTOR: &'static str = ".chapter_title";
+// Only a few novels have these under the section's name
+const SECTION_DESCRIPTION_SELECTOR: &'static str = ".chapter_title + p";
 const CHAPTER_SELECTOR: &'static str = ".novel_sublist2";
+const LAST_PAGE_SELECTOR: &'static str = "a.novelview_pager-last";
 
-pub fn make_syosetu_novel(uri: Uri) -> NovelResult<Novel> {
-    let node = crate::fetch_page(&uri)?;
-    let mut main_page_data = TreeTraverser::new(node, MainPageData::default())
-        .add_hook(TITLE_SELECTOR, None, MainPageData::get_title)?
-        .add_hook(AUTHOR_SELECTOR, None, MainPageData::get_author)?
-        .add_hook(INFO_LINK_SELECTOR, None, MainPageData::get_info_path)?
-        .add_hook(SECTION_SELECTOR, None, MainPageData::get_section)?
-        .add_hook(CHAPTER_SELECTOR, None, MainPageData::get_chapter)?
-        .traverse();
-    main_page_data.append_chapters_to_section();
+pub fn make_syosetu_novel(uri: Uri, novel_info: &NovelInfo, observer: &dyn ProgressObserver)
+-> NovelResult<Novel> {
+    let selectors = &novel_info.selectors;
+    let mut main_page_data = fetch_main_page(&uri, novel_info)?;
+    let content_selectors = selectors.content(&content::CONTENT_SELECTORS);
 
-    let title = main_page_data.title.ok_or(NovelError::ComponentMissing(NovelComponent::Title))?;
-    let author = main_page_data.author.ok_or(NovelError::ComponentMissing(NovelComponent::Author))?;
-    let info_path = main_page_data.info_path
-        .ok_or(NovelError::ComponentMissing(NovelComponent::InfoPath))?;
-    let status = info_page::fetch_status_in_info(make_uri(&info_path)?)?;
-    let contents = {
-        if main_page_data.sections.is_empty() {
-            if main_page_data.chapters.is_empty() {
-                return Err(NovelError::ComponentMissing(NovelComponent::Chapter));
-            }
-            let chapters = fetch_chapters(main_page_data.chapters)?;
-            NovelContents::Chapters(chapters)
-        } else {
-            for section in main_page_data.sections.iter() {
-                if section.chapters.is_empty() {
-                    return Err(NovelError::ComponentMissing(NovelComponent::ChapterUnderSection));
-                }
-            }
-            let sections = fetch_sections(main_page_data.sections)?;
-            NovelContents::Sections(sections)
-        }
-    };
+    let overview = main_page_data.take_overview()?;
+    if let Some(latest) = novel_info.latest {
+        novel::keep_latest_chapters(&mut main_page_data.sections, &mut main_page_data.chapters,
+            latest, SectionInfo::chapters_mut);
+    }
+    let contents = novel::assemble_contents(main_page_data.sections, main_page_data.chapters,
+        novel_info.drop_empty_sections,
+        |section| section.chapters.is_empty(),
+        |sections| fetch_sections(sections, &content_selectors, observer),
+        |chapters| fetch_chapters(chapters, &content_selectors, observer),
+    )?;
 
     Ok(Novel {
-        title,
-        author,
-        status,
+        title: overview.title,
+        title_ruby: overview.title_ruby,
+        author: overview.author,
+        status: overview.status,
+        ratings: overview.ratings,
+        keywords: overview.keywords,
         source_url: uri.to_string(),
+        chapter_counter: "話".to_string(),
+        reading_dir: novel_info.reading_dir,
         contents,
     })
 }
 
+// This still needs the info page for the status and keywords, but none of the chapters
+pub fn fetch_syosetu_overview(uri: Uri, novel_info: &NovelInfo)
+-> NovelResult<NovelOverview> {
+    fetch_main_page(&uri, novel_info)?.take_overview()
+}
+
+pub fn fetch_syosetu_title(uri: Uri, novel_info: &NovelInfo) -> NovelResult<String> {
+    novel_utils::fetch_title(&uri, &[novel_info.selectors.title(TITLE_SELECTOR)])
+}
+
+pub fn fetch_syosetu_chapter_list(uri: Uri, novel_info: &NovelInfo)
+-> NovelResult< Vec<ChapterManifest> > {
+    fetch_main_page(&uri, novel_info)?.into_chapter_list()
+}
+
+// Both only need the table of contents (and the info page), so they only get fetched once
+pub fn fetch_syosetu_overview_and_chapters(uri: &Uri, novel_info: &NovelInfo)
+-> NovelResult<(NovelOverview, Vec<ChapterManifest>)> {
+    let mut main_page_data = fetch_main_page(uri, novel_info)?;
+    let overview = main_page_data.take_overview()?;
+    Ok((overview, main_page_data.into_chapter_list()?))
+}
+
+// The novel without any chapters, along with the list of the chapters that it has
+pub fn fetch_syosetu_stub(uri: Uri, novel_info: &NovelInfo)
+-> NovelResult<(Novel, Vec<ChapterManifest>)> {
+    let (overview, chapters) = fetch_syosetu_overview_and_chapters(&uri, novel_info)?;
+    let novel = Novel {
+        title: overview.title,
+        title_ruby: overview.title_ruby,
+        author: overview.author,
+        status: overview.status,
+        ratings: overview.ratings,
+        keywords: overview.keywords,
+        source_url: uri.to_string(),
+        chapter_counter: "話".to_string(),
+        reading_dir: novel_info.reading_dir,
+        contents: novel::NovelContents::Chapters(Vec::new()),
+    };
+    Ok((novel, chapters))
+}
+
+fn fetch_main_page(uri: &Uri, novel_info: &NovelInfo) -> NovelResult<MainPageData> {
+    let selectors = &novel_info.selectors;
+    let first_page = crate::fetch_page(uri)?;
+    novel_utils::check_login_wall(&first_page, uri, selectors.title(TITLE_SELECTOR))?;
+    let mut main_page_data = parse_toc_page(first_page, selectors, MainPageData::default())?;
+    // Long novels split up their table of contents over many pages
+    //  Going through them with the same data keeps the sections and chapter numbers going
+    let last_page = main_page_data.last_page.unwrap_or(1);
+    for page in 2..=last_page {
+        let page_uri = make_uri(&format!("{}?p={}", uri.path(), page))?;
+        main_page_data = parse_toc_page(crate::fetch_page(&page_uri)?, selectors, main_page_data)?;
+    }
+    main_page_data.append_chapters_to_section();
+    main_page_data.name_unnamed_chapters(&novel_info.fallback_chapter_name);
+    Ok(main_page_data)
+}
+// Every page of the table of contents goes into the same data, one after another
+//  Chapters with an empty name are left that way
+pub fn parse_toc_page(node: NodeRef, selectors: &SelectorOverrides, main_page_data: MainPageData)
+-> NovelResult<MainPageData> {
+    Ok(TreeTraverser::new(node, main_page_data)
+        .add_hook(selectors.title(TITLE_SELECTOR), None, MainPageData::get_title)?
+        .add_hook(selectors.author(AUTHOR_SELECTOR), None, MainPageData::get_author)?
+        .add_hook(AUTHOR_FALLBACK_SELECTOR, None, MainPageData::get_author_fallback)?
+        .add_hook(SYNOPSIS_SELECTOR, None, MainPageData::get_synopsis)?
+        .add_hook(INFO_LINK_SELECTOR, None, MainPageData::get_info_path)?
+        .add_hook(LAST_PAGE_SELECTOR, None, MainPageData::get_last_page)?
+        .add_hook(selectors.section(SECTION_SELECTOR), None, MainPageData::get_section)?
+        .add_hook(selectors.section_description(SECTION_DESCRIPTION_SELECTOR), None,
+            MainPageData::get_section_description)?
+        .add_hook(selectors.chapter(CHAPTER_SELECTOR), None, MainPageData::get_chapter)?
+        .traverse())
+}
+
+// Everything that was parsed is left open, so that a saved page can be checked without fetching
 #[derive(Debug, Default)]
-struct MainPageData {
-    title: Option<String>,
-    author: Option<String>,
-    info_path: Option<String>,
-    sections: Vec<SectionInfo>,
-    chapters: Vec<ChapterInfo>,
+pub struct MainPageData {
+    pub title: Option<String>,
+    pub title_ruby: Option< Vec<Content> >,
+    pub author: Option<String>,
+    // Only used when the author doesn't have a link
+    pub author_fallback: Option<String>,
+    pub synopsis: Option<String>,
+    pub info_path: Option<String>,
+    // Only there when the table of contents has more than 1 page
+    pub last_page: Option<u32>,
+    // Chapters only stay here until the next section (or the end of the last page)
+    pub sections: Vec<SectionInfo>,
+    pub chapters: Vec<ChapterInfo>,
     chapter_count: u32,
 }
 impl MainPageData {
+    // Every chapter needs a name for the table of contents, even if the site didn't give it one
+    fn name_unnamed_chapters(&mut self, fallback_chapter_name: &str) {
+        let section_chapters = self.sections.iter_mut()
+            .flat_map(|section| section.chapters.iter_mut());
+        for chapter in section_chapters.chain(self.chapters.iter_mut()) {
+            chapter.name = novel_utils::name_or_fallback(mem::take(&mut chapter.name),
+                chapter.order_num, fallback_chapter_name);
+        }
+    }
     fn append_chapters_to_section(&mut self) {
         if let Some(section) = self.sections.last_mut() {
             section.chapters.append(&mut self.chapters);
@@ -99,13 +195,55 @@ impl MainPageData {
         self.chapter_count += 1;
         self.chapter_count
     }
+    fn into_chapter_list(self) -> NovelResult< Vec<ChapterManifest> > {
+        self.sections.into_iter()
+            .flat_map(|section| section.chapters)
+            .chain(self.chapters)
+            .map(ChapterInfo::into_manifest)
+            .collect()
+    }
+    fn take_overview(&mut self) -> NovelResult<NovelOverview> {
+        let title = self.title.take()
+            .ok_or(NovelError::ComponentMissing(NovelComponent::Title))?;
+        let author = self.author.take().or(self.author_fallback.take())
+            .ok_or(NovelError::ComponentMissing(NovelComponent::Author))?;
+        let info_path = self.info_path.take()
+            .ok_or(NovelError::ComponentMissing(NovelComponent::InfoPath))?;
+        let info_page = info_page::fetch_info_page(make_uri(&info_path)?)?;
+        let parsed_count = self.chapters.len() + self.sections.iter()
+            .map(|section| section.chapters.len())
+            .sum::<usize>();
+        novel_utils::warn_if_chapter_count_differs(info_page.stated_chapter_count, parsed_count);
+        Ok(NovelOverview {
+            title,
+            title_ruby: self.title_ruby.take(),
+            author,
+            status: info_page.status,
+            synopsis: self.synopsis.take(),
+            ratings: info_page.ratings,
+            keywords: info_page.keywords,
+            chapter_count: self.chapter_count,
+        })
+    }
 
     fn get_title(&mut self, element: &NodeDataRef<ElementData>) {
-        self.title = Some(element.text_contents());
+        self.title_ruby = novel_utils::get_ruby_text(element);
+        self.title = Some(match self.title_ruby.as_ref() {
+            Some(title_ruby) => novel_utils::base_text(title_ruby),
+            None => element.text_contents(),
+        });
     }
     fn get_author(&mut self, element: &NodeDataRef<ElementData>) {
         self.author = Some(element.text_contents());
     }
+    fn get_author_fallback(&mut self, element: &NodeDataRef<ElementData>) {
+        let author_text = element.text_contents();
+        let author = author_text.trim().trim_start_matches("作者：").trim();
+        self.author_fallback = Some(author.to_string());
+    }
+    fn get_synopsis(&mut self, element: &NodeDataRef<ElementData>) {
+        self.synopsis = Some(element.text_contents().trim().to_string());
+    }
     fn get_info_path(&mut self, element: &NodeDataRef<ElementData>) {
         if &element.text_contents() == "小説情報" {
             let attributes = element.attributes.borrow();
@@ -115,40 +253,63 @@ impl MainPageData {
         }
     }
 
+    fn get_last_page(&mut self, element: &NodeDataRef<ElementData>) {
+        let attributes = element.attributes.borrow();
+        if let Some(href) = attributes.get("href") {
+            // The link looks like /n1234ab/?p=5
+            let page: String = href.rsplit("p=").next().unwrap_or("").chars()
+                .take_while(char::is_ascii_digit)
+                .collect();
+            self.last_page = page.parse().ok();
+        }
+    }
+
     fn get_section(&mut self, element: &NodeDataRef<ElementData>) {
         self.append_chapters_to_section();
         self.sections.push(SectionInfo {
             name: element.text_contents(),
+            description: None,
             chapters: Vec::new(),
         });
     }
+    fn get_section_description(&mut self, element: &NodeDataRef<ElementData>) {
+        let description = element.text_contents().trim().to_string();
+        if let Some(section) = self.sections.last_mut() {
+            if !description.is_empty() {
+                section.description = Some(description);
+            }
+        }
+    }
     fn get_chapter(&mut self, element: &NodeDataRef<ElementData>) {
         let node = element.as_node();
-        let name_node = node.select_first(".subtitle > a").unwrap();
-        let date_node = node.select_first(".long_update").unwrap();
-
-        let mut date_children = date_node.as_node().children();
-        let uploaded_date = {
-            let uploaded_date_node = date_children.next().unwrap().into_text_ref().unwrap();
-            let borrowed = uploaded_date_node.borrow();
-            borrowed.to_string()
-        };
-        let date = {
-            if let Some(edited_node) = date_children.next() {
-                let edited_node = edited_node.into_element_ref().unwrap();
-                let attributes = edited_node.attributes.borrow();
-                let edit_date = attributes.get("title").unwrap().to_string();
-                format!("{}（{}）", uploaded_date, edit_date)
-            } else {
-                uploaded_date
-            }
-        };
-
+        // Even skipped chapters need a number so that the rest still line up with the site
         let order_num = self.increment_and_get_chapter_count();
+
+        let name_node = match node.select_first(".subtitle > a") {
+            Ok(name_node) => name_node,
+            Err(_) => {
+                println!("Skipping chapter {} since it has no link", order_num);
+                return;
+            },
+        };
         let content_path = {
             let attributes = name_node.attributes.borrow();
-            attributes.get("href").unwrap().to_string()
+            match attributes.get("href") {
+                Some(href) => href.to_string(),
+                None => {
+                    println!("Skipping chapter {} since its link goes nowhere", order_num);
+                    return;
+                },
+            }
         };
+        // The chapter is still readable without a date
+        let date = node.select_first(".long_update").ok()
+            .and_then(|date_node| parse_chapter_date(date_node.as_node()))
+            .unwrap_or_else(|| {
+                println!("Couldn't find the date for chapter {}", order_num);
+                String::new()
+            });
+
         self.chapters.push(ChapterInfo {
             name: name_node.text_contents(),
             date,
@@ -157,42 +318,84 @@ impl MainPageData {
         });
     }
 }
+// The date starts with the uploaded date as text
+//  If it has been edited, there will also be an element with the edited date as its title
+fn parse_chapter_date(date_node: &NodeRef) -> Option<String> {
+    let mut date_children = date_node.children();
+    // The layout puts the date on its own line
+    let uploaded_date = date_children.next()?.as_text()?.borrow().trim().to_string();
+    if uploaded_date.is_empty() {
+        return None;
+    }
+    let edit_date = date_children
+        .filter_map(|child| child.into_element_ref())
+        .find_map(|edited_element| {
+            let attributes = edited_element.attributes.borrow();
+            let edit_date = attributes.get("title").map(|title| title.trim().to_string());
+            edit_date
+        });
+    Some(match edit_date {
+        Some(edit_date) => format!("{}（{}）", uploaded_date, edit_date),
+        None => uploaded_date,
+    })
+}
+
 #[derive(Debug, Default)]
-struct SectionInfo {
-    name: String,
-    chapters: Vec<ChapterInfo>,
+pub struct SectionInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub chapters: Vec<ChapterInfo>,
 }
 impl SectionInfo {
-    fn fetch(self) -> NovelResult<Section> {
-        let chapters = fetch_chapters(self.chapters)?;
+    fn chapters_mut(&mut self) -> &mut Vec<ChapterInfo> { &mut self.chapters }
+    fn fetch(self, content_selectors: &[String], observer: &dyn ProgressObserver)
+    -> NovelResult<Section> {
+        let chapters = fetch_chapters(self.chapters, content_selectors, observer)?;
         Ok(Section {
             name: self.name,
+            description: self.description,
             chapters,
         })
     }
 }
 #[derive(Debug, Default)]
-struct ChapterInfo {
-    name: String,
-    date: String,
-    order_num: u32,
-    content_path: String,
+pub struct ChapterInfo {
+    pub name: String,
+    pub date: String,
+    pub order_num: u32,
+    pub content_path: String,
 }
 impl ChapterInfo {
-    fn fetch(self) -> NovelResult<Chapter> {
-        let content = content::fetch_page_content(make_uri(&self.content_path)?)?;
+    fn into_manifest(self) -> NovelResult<ChapterManifest> {
+        Ok(ChapterManifest {
+            order_num: self.order_num,
+            name: self.name,
+            date: self.date,
+            source_url: make_uri(&self.content_path)?.to_string(),
+        })
+    }
+    fn fetch(self, content_selectors: &[String], observer: &dyn ProgressObserver)
+    -> NovelResult<Chapter> {
+        let uri = make_uri(&self.content_path)?;
+        let source_url = uri.to_string();
+        let (content, author_notes) = content::fetch_page_content(uri, content_selectors)?;
+        observer.on_chapter_fetched(self.order_num, &self.name);
         Ok(Chapter {
             name: self.name,
             date: self.date,
             order_num: self.order_num,
+            source_url,
+            tags: Vec::new(),
             content,
+            author_notes,
         })
     }
 }
 // NOTE This needs to take a long time since they start cutting us off
-fn fetch_sections(section_infos: Vec<SectionInfo>) -> NovelResult< Vec<Section> > {
+fn fetch_sections(section_infos: Vec<SectionInfo>, content_selectors: &[String],
+observer: &dyn ProgressObserver) -> NovelResult< Vec<Section> > {
     let results: Vec<_> = section_infos.into_iter()
-        .map(|section| section.fetch())
+        .map(|section| section.fetch(content_selectors, observer))
         .collect();
     let mut sections = Vec::new();
     for result in results {
@@ -200,9 +403,10 @@ fn fetch_sections(section_infos: Vec<SectionInfo>) -> NovelResult< Vec<Section>
     }
     Ok(sections)
 }
-fn fetch_chapters(chapter_infos: Vec<ChapterInfo>) -> NovelResult< Vec<Chapter> > {
+fn fetch_chapters(chapter_infos: Vec<ChapterInfo>, content_selectors: &[String],
+observer: &dyn ProgressObserver) -> NovelResult< Vec<Chapter> > {
     let results: Vec<_> = chapter_infos.into_iter()
-        .map(|chapter| chapter.fetch())
+        .map(|chapter| chapter.fetch(content_selectors, observer))
         .collect();
     let mut chapters = Vec::new();
     for result in results {
@@ -210,3 +414,128 @@ fn fetch_chapters(chapter_infos: Vec<ChapterInfo>) -> NovelResult< Vec<Chapter>
     }
     Ok(chapters)
 }
+
+#[cfg(test)]
+mod tests {
+    use kuchiki::traits::*;
+
+    use super::*;
+
+    // One chapter that was edited, one that wasn't, and one without any date at all
+    const TOC_FIXTURE: &'static str = r#"<div class="index_box">
+<dl class="novel_sublist2">
+<dd class="subtitle"><a href="/n1234ab/1/">第一話</a></dd>
+<dt class="long_update">
+2019/01/01 00:00<span title="2019/02/03 04:05 改稿">（<u>改</u>）</span>
+</dt>
+</dl>
+<dl class="novel_sublist2">
+<dd class="subtitle"><a href="/n1234ab/2/">第二話</a></dd>
+<dt class="long_update">
+2019/01/02 00:00
+</dt>
+</dl>
+<dl class="novel_sublist2">
+<dd class="subtitle"><a href="/n1234ab/3/">第三話</a></dd>
+</dl>
+</div>"#;
+
+    fn parse_chapters(toc_html: &str) -> Vec<ChapterInfo> {
+        let page_node = kuchiki::parse_html().one(toc_html);
+        let main_page_data = parse_toc_page(page_node, &SelectorOverrides::default(),
+            MainPageData::default()).expect("The table of contents couldn't be parsed");
+        main_page_data.chapters
+    }
+
+    #[test]
+    fn the_table_of_contents_is_parsed_without_fetching() {
+        let page_node = kuchiki::parse_html().one(r#"<ul id="head_nav">
+<li><a href="/n1234ab/">小説</a></li>
+<li><a href="/novelview/infotop/ncode/n1234ab/">小説情報</a></li>
+</ul>
+<p class="novel_title">魔法使いの旅</p>
+<div class="novel_writername">作者：<a href="/users/1/">作者</a></div>
+<div id="novel_ex">旅に出る話です。</div>
+<div class="index_box">
+<div class="chapter_title">第一章</div>
+<dl class="novel_sublist2">
+<dd class="subtitle"><a href="/n1234ab/1/">第一話</a></dd>
+<dt class="long_update">2019/01/01 00:00</dt>
+</dl>
+<div class="chapter_title">第二章</div>
+<dl class="novel_sublist2">
+<dd class="subtitle"><a href="/n1234ab/2/">第二話</a></dd>
+<dt class="long_update">2019/01/02 00:00</dt>
+</dl>
+</div>"#);
+        let mut main_page_data = parse_toc_page(page_node, &SelectorOverrides::default(),
+            MainPageData::default()).expect("The table of contents couldn't be parsed");
+        assert_eq!(main_page_data.title.as_ref().map(String::as_str), Some("魔法使いの旅"));
+        assert_eq!(main_page_data.author.as_ref().map(String::as_str), Some("作者"));
+        assert_eq!(main_page_data.synopsis.as_ref().map(String::as_str), Some("旅に出る話です。"));
+        assert_eq!(main_page_data.info_path.as_ref().map(String::as_str),
+            Some("/novelview/infotop/ncode/n1234ab/"));
+        assert_eq!(main_page_data.last_page, None);
+
+        // The last section only gets its chapters once every page has been parsed
+        main_page_data.append_chapters_to_section();
+        let section_names: Vec<&str> = main_page_data.sections.iter()
+            .map(|section| section.name.as_str())
+            .collect();
+        assert_eq!(section_names, vec!["第一章", "第二章"]);
+        assert_eq!(main_page_data.sections[0].chapters[0].content_path, "/n1234ab/1/");
+        assert_eq!(main_page_data.sections[1].chapters[0].name, "第二話");
+        assert_eq!(main_page_data.sections[1].chapters[0].order_num, 2);
+    }
+
+    #[test]
+    fn title_with_ruby_uses_its_base_for_the_title() {
+        let page_node = kuchiki::parse_html().one(
+            "<p class=\"novel_title\"><ruby>魔法<rt>まほう</rt></ruby>使いの旅</p>");
+        let title_element = page_node.select_first(".novel_title").unwrap();
+        let mut main_page_data = MainPageData::default();
+        main_page_data.get_title(&title_element);
+        assert_eq!(main_page_data.title.as_ref().map(String::as_str), Some("魔法使いの旅"));
+        assert_eq!(main_page_data.title_ruby, Some(vec![
+            Content::Ruby { main: "魔法".to_string(), above: "まほう".to_string() },
+            Content::Span("使いの旅".to_string()),
+        ]));
+    }
+    #[test]
+    fn title_without_ruby_has_no_title_ruby() {
+        let page_node = kuchiki::parse_html().one("<p class=\"novel_title\">魔法使いの旅</p>");
+        let title_element = page_node.select_first(".novel_title").unwrap();
+        let mut main_page_data = MainPageData::default();
+        main_page_data.get_title(&title_element);
+        assert_eq!(main_page_data.title.as_ref().map(String::as_str), Some("魔法使いの旅"));
+        assert_eq!(main_page_data.title_ruby, None);
+    }
+
+    #[test]
+    fn edited_chapters_have_both_dates() {
+        let chapters = parse_chapters(TOC_FIXTURE);
+        assert_eq!(chapters[0].date, "2019/01/01 00:00（2019/02/03 04:05 改稿）");
+    }
+    #[test]
+    fn chapters_that_were_not_edited_only_have_the_uploaded_date() {
+        let chapters = parse_chapters(TOC_FIXTURE);
+        assert_eq!(chapters[1].date, "2019/01/02 00:00");
+    }
+    #[test]
+    fn chapters_without_a_date_are_still_kept() {
+        let chapters = parse_chapters(TOC_FIXTURE);
+        assert_eq!(chapters.len(), 3);
+        assert_eq!(chapters[2].name, "第三話");
+        assert_eq!(chapters[2].date, "");
+        assert_eq!(chapters[2].order_num, 3);
+    }
+    #[test]
+    fn an_empty_date_has_nothing_to_parse() {
+        let page_node = kuchiki::parse_html().one("<dl><dt class=\"long_update\"> </dt></dl>");
+        let date_node = page_node.select_first(".long_update").unwrap();
+        assert_eq!(parse_chapter_date(date_node.as_node()), None);
+        let page_node = kuchiki::parse_html().one("<dl><dt class=\"long_update\"></dt></dl>");
+        let date_node = page_node.select_first(".long_update").unwrap();
+        assert_eq!(parse_chapter_date(date_node.as_node()), None);
+    }
+}
@@ -1,53 +1,187 @@
+use std::{
+    cell::{RefCell},
+};
 use isahc::http::Uri;
-use kuchiki::{ElementData, NodeData, NodeDataRef};
+use kuchiki::{ElementData, NodeDataRef, NodeRef};
 
 use crate::{
     NovelComponent, NovelError, NovelResult,
     novel::{
-        Content, ContentLine,
-        novel_utils,
+        AuthorNotes, ContentLine,
+        novel_utils::{self, ContentData, ParagraphDepth},
     },
     traverser::{TreeTraverser},
 };
 
-const LINE_SELECTOR: &'static str = "#novel_honbun > p";
-const BLANK_SELECTOR: &'static str = "#novel_honbun > p > br";
+// The newer layout keeps the 前書き and 後書き in the same kind of element as the contents
+pub const CONTENT_SELECTORS: [&'static str; 2] = [
+    "#novel_honbun",
+    ".p-novel__text:not(.p-novel__text--preface):not(.p-novel__text--afterword)",
+];
+// 前書き and 後書き
+const PREFACE_SELECTOR: &'static str = "#novel_p";
+const AFTERWORD_SELECTOR: &'static str = "#novel_a";
+// A few long episodes are split over many pages, like /n1234ab/5/?p=2
+const EPISODE_PAGE_LINK_SELECTOR: &'static str = "a[href*=\"p=\"]";
 
-pub fn fetch_page_content(uri: Uri) -> NovelResult< Vec<ContentLine> > {
-    let page_node = crate::fetch_page(&uri)?;
-    let content_data = TreeTraverser::new(page_node, ContentData::default())
-        .add_hook(LINE_SELECTOR, None, ContentData::get_line)?
-        .add_hook(BLANK_SELECTOR, None, ContentData::get_blank)?
-        .traverse();
-    if content_data.lines.is_empty() {
+pub fn fetch_page_content(uri: Uri, content_selectors: &[String])
+-> NovelResult<(Vec<ContentLine>, AuthorNotes)> {
+    let last_short_lines = RefCell::new(None);
+    let (page_node, mut lines, matched_selector) = crate::fetch_page_with(&uri, |page_node| {
+        let (mut lines, matched_selector) = novel_utils::parse_any_content(&page_node,
+            content_selectors, parse_content)?;
+        // A busy page still has a sentence or two in the content
+        if !lines.is_empty() {
+            let char_count = lines.iter().map(ContentLine::char_count).sum();
+            lines = crate::fetcher().check_content_length(&uri, lines, char_count,
+                &last_short_lines)?;
+        }
+        Ok((page_node, lines, matched_selector))
+    })?;
+    // The rest of the episode's pages will have the same layout
+    let (content_selector, depth) = novel_utils::log_content_selector(&uri, content_selectors,
+        matched_selector);
+    if lines.is_empty() {
+        lines = novel_utils::fetch_embedded_content(&page_node, content_selector, depth, &uri,
+            parse_content)?;
+    }
+    if lines.is_empty() {
         println!("Couldn't get contents of {:?}", &uri);
         return Err(NovelError::ComponentMissing(NovelComponent::ChapterContent));
     }
+    // The 前書き is on the first page and the 後書き is on the last one
+    let mut author_notes = parse_author_notes(page_node.clone())?;
+    let last_page = find_episode_last_page(&page_node, &uri);
+    for page in 2..=last_page {
+        let page_uri = super::make_uri(&format!("{}?p={}", uri.path(), page))?;
+        let (page_node, page_lines) = crate::fetch_page_with(&page_uri, |page_node| {
+            Ok((page_node.clone(), parse_content(page_node, content_selector, depth)?))
+        })?;
+        lines.extend(page_lines);
+        if page == last_page {
+            author_notes.after = parse_author_notes(page_node)?.after;
+        }
+    }
+    if last_page > 1 {
+        println!("Put together {} pages of {}", last_page, &uri);
+    }
+    Ok((lines, author_notes))
+}
+// Only counts the links to other pages of this same episode
+fn find_episode_last_page(page_node: &NodeRef, uri: &Uri) -> u32 {
+    let page_links = match page_node.select(EPISODE_PAGE_LINK_SELECTOR) {
+        Ok(page_links) => page_links,
+        Err(_) => return 1,
+    };
+    page_links
+        .filter_map(|link| {
+            let href = link.attributes.borrow().get("href")?.to_string();
+            let link_uri = novel_utils::resolve_link(uri, &href).ok()?;
+            if link_uri.path() != uri.path() {
+                return None;
+            }
+            link_uri.query()?.split('&')
+                .find_map(|param| param.strip_prefix("p="))?
+                .parse().ok()
+        })
+        .max()
+        .unwrap_or(1)
+}
+pub fn parse_author_notes(page_node: NodeRef) -> NovelResult<AuthorNotes> {
+    Ok(AuthorNotes {
+        before: parse_content(page_node.clone(), PREFACE_SELECTOR, ParagraphDepth::Child)?,
+        after: parse_content(page_node, AFTERWORD_SELECTOR, ParagraphDepth::Child)?,
+    })
+}
+pub fn parse_content(page_node: NodeRef, content_selector: &str, depth: ParagraphDepth)
+-> NovelResult< Vec<ContentLine> > {
+    let line_selector = depth.inside(content_selector, "p");
+    let blank_selector = depth.inside(content_selector, "p > br");
+    let nested_selector = depth.nested_selector(content_selector);
+    let nested_neg = nested_selector.as_ref().map(|nested_selector| nested_selector.as_str());
+
+    let traverser = TreeTraverser::new(page_node, ContentData::default())
+        .add_hook(&line_selector, nested_neg, get_line)?
+        .add_hook(&blank_selector, nested_neg, get_blank)?;
+    let content_data = novel_utils::add_block_hooks(traverser, content_selector, depth,
+        nested_neg)?.traverse();
     Ok(content_data.lines)
 }
 
-#[derive(Debug, Default)]
-struct ContentData {
-    lines: Vec<ContentLine>,
+fn get_line(content_data: &mut ContentData, element: &NodeDataRef<ElementData>) {
+    let contents = novel_utils::get_line_contents(element.as_node());
+    if !contents.is_empty() {
+        let align = novel_utils::get_line_align(element);
+        content_data.lines.push(ContentLine::Line(contents, align));
+    }
 }
-impl ContentData {
-    fn get_line(&mut self, element: &NodeDataRef<ElementData>) {
-        let mut contents: Vec<Content> = Vec::new();
-        for child in element.as_node().children() {
-            match child.data() {
-                NodeData::Text(text) => contents.push(Content::Span(text.borrow().to_string())),
-                NodeData::Element(child_element) => {
-                    let mut ruby_contents = novel_utils::get_ruby(&child, &child_element);
-                    contents.append(&mut ruby_contents);
-                },
-                _ => (),
-            }
-        }
-        if !contents.is_empty() {
-            self.lines.push(ContentLine::Line(contents));
-        }
+fn get_blank(content_data: &mut ContentData, _element: &NodeDataRef<ElementData>) {
+    content_data.lines.push(ContentLine::Blank);
+}
+
+#[cfg(test)]
+mod tests {
+    use kuchiki::traits::*;
+
+    use crate::novel::{Content, LineAlign};
+    use super::*;
+
+    fn line(text: &str) -> ContentLine {
+        ContentLine::Line(vec![Content::Span(text.to_string())], LineAlign::Start)
+    }
+
+    // The older layout, with the 前書き and 後書き around the contents
+    const EPISODE_FIXTURE: &'static str = r#"<div id="novel_p" class="novel_view"><p>前書きです</p></div>
+<div id="novel_honbun" class="novel_view">
+<p id="L1">一行目</p>
+<p id="L2"><br></p>
+<p id="L3"><ruby>魔法<rp>(</rp><rt>まほう</rt><rp>)</rp></ruby>を使った</p>
+</div>
+<div id="novel_a" class="novel_view"><p>後書きです</p></div>"#;
+
+    #[test]
+    fn the_episode_is_parsed_without_fetching() {
+        let page_node = kuchiki::parse_html().one(EPISODE_FIXTURE);
+        let lines = parse_content(page_node, CONTENT_SELECTORS[0], ParagraphDepth::Child)
+            .expect("The episode couldn't be parsed");
+        assert_eq!(lines, vec![
+            line("一行目"),
+            ContentLine::Blank,
+            ContentLine::Line(vec![
+                Content::Ruby { main: "魔法".to_string(), above: "まほう".to_string() },
+                Content::Span("を使った".to_string()),
+            ], LineAlign::Start),
+        ]);
+    }
+    #[test]
+    fn the_author_notes_are_parsed_without_fetching() {
+        let page_node = kuchiki::parse_html().one(EPISODE_FIXTURE);
+        let author_notes = parse_author_notes(page_node).expect("The notes couldn't be parsed");
+        assert_eq!(author_notes.before, vec![line("前書きです")]);
+        assert_eq!(author_notes.after, vec![line("後書きです")]);
     }
-    fn get_blank(&mut self, _element: &NodeDataRef<ElementData>) {
-        self.lines.push(ContentLine::Blank);
+    #[test]
+    fn the_newer_layout_is_found_with_its_lists_and_tables() {
+        let page_node = kuchiki::parse_html().one(r#"<div class="p-novel__text">
+<p>一行目</p>
+<ul><li>魔力：１０</li></ul>
+<table><tr><th>名前</th><td>アリス</td></tr></table>
+</div>"#);
+        let content_selectors: Vec<String> = CONTENT_SELECTORS.iter()
+            .map(|selector| selector.to_string())
+            .collect();
+        let (lines, matched_selector) = novel_utils::parse_any_content(&page_node,
+            &content_selectors, parse_content).expect("The episode couldn't be parsed");
+        assert_eq!(matched_selector, Some((CONTENT_SELECTORS[1], ParagraphDepth::Child)));
+        assert_eq!(lines.len(), 3, "Lines: {:?}", lines);
+        assert_eq!(lines[0], line("一行目"));
+        assert_eq!(lines[1], ContentLine::List {
+            ordered: false,
+            items: vec![vec![Content::Span("魔力：１０".to_string())]],
+        });
+        match &lines[2] {
+            ContentLine::Table(rows) => assert_eq!(rows.len(), 1),
+            other_line => panic!("The table became {:?}", other_line),
+        }
     }
 }
@@ -3,27 +3,49 @@ use kuchiki::{ElementData, NodeDataRef};
 
 use crate::{
     NovelComponent, NovelError, NovelResult,
-    novel::{NovelStatus},
+    novel::{NovelStatus, novel_utils},
     traverser::{TreeTraverser},
 };
 
 const FINISHED_SELECTOR: &'static str = "#noveltype";
 const RUNNING_SELECTOR: &'static str = "#noveltype_notend";
+const INFO_ROW_SELECTOR: &'static str = "#noveltable1 tr";
+const PRE_INFO_SELECTOR: &'static str = "#pre_info";
+// Syosetu puts these in with the rest of the keywords
+const RATING_KEYWORDS: [&'static str; 4] = ["R15", "残酷な描写あり", "ボーイズラブ", "ガールズラブ"];
 
-pub fn fetch_status_in_info(uri: Uri) -> NovelResult<NovelStatus> {
+pub struct InfoPage {
+    pub status: NovelStatus,
+    pub ratings: Vec<String>,
+    pub keywords: Vec<String>,
+    pub stated_chapter_count: Option<u32>,
+}
+
+pub fn fetch_info_page(uri: Uri) -> NovelResult<InfoPage> {
     let node = crate::fetch_page(&uri)?;
     let info_page_data = TreeTraverser::new(node, InfoPageData::default())
         .add_hook(FINISHED_SELECTOR, None, InfoPageData::get_finished)?
         .add_hook(RUNNING_SELECTOR, None, InfoPageData::get_running)?
+        .add_hook(INFO_ROW_SELECTOR, None, InfoPageData::get_keywords)?
+        .add_hook(PRE_INFO_SELECTOR, None, InfoPageData::get_stated_chapter_count)?
         .traverse();
     let status = info_page_data.status
         .ok_or(NovelError::ComponentMissing(NovelComponent::Status))?;
-    Ok(status)
+    let (ratings, keywords): (Vec<String>, Vec<String>) = info_page_data.keywords.into_iter()
+        .partition(|keyword| RATING_KEYWORDS.contains(&keyword.as_str()));
+    Ok(InfoPage {
+        status,
+        ratings,
+        keywords,
+        stated_chapter_count: info_page_data.stated_chapter_count,
+    })
 }
 
 #[derive(Debug, Default)]
 struct InfoPageData {
     status: Option<NovelStatus>,
+    keywords: Vec<String>,
+    stated_chapter_count: Option<u32>,
 }
 impl InfoPageData {
     fn get_finished(&mut self, _element: &NodeDataRef<ElementData>) {
@@ -32,4 +54,30 @@ impl InfoPageData {
     fn get_running(&mut self, _element: &NodeDataRef<ElementData>) {
         self.status = Some(NovelStatus::Running);
     }
+    // Finished novels have 全123部分 somewhere in the text, but it's missing for short stories
+    fn get_stated_chapter_count(&mut self, element: &NodeDataRef<ElementData>) {
+        let pre_info_text = element.text_contents();
+        self.stated_chapter_count = pre_info_text.find('全').and_then(|start| {
+            let after = &pre_info_text[start + '全'.len_utf8()..];
+            let end = after.find("部分")?;
+            novel_utils::parse_count(&after[..end])
+        });
+    }
+    // Every row looks the same, so the header is the only way to find the keywords
+    fn get_keywords(&mut self, element: &NodeDataRef<ElementData>) {
+        let row = element.as_node();
+        let is_keyword_row = row.select_first("th")
+            .map_or(false, |header| header.text_contents().trim() == "キーワード");
+        if !is_keyword_row {
+            return;
+        }
+        if let Ok(keyword_cell) = row.select_first("td") {
+            // They're split up by normal, full width and non-breaking spaces
+            self.keywords = keyword_cell.text_contents()
+                .split(char::is_whitespace)
+                .filter(|keyword| !keyword.is_empty())
+                .map(|keyword| keyword.to_string())
+                .collect();
+        }
+    }
 }
@@ -0,0 +1,110 @@
+use std::{
+    collections::{BTreeMap},
+    fs,
+    path::{Path},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    NovelResult,
+    novel::{Section, VolumeStrategy},
+};
+
+// Lives in the save directory next to the books, just like the novel statuses
+const VOLUME_FILE_NAME: &'static str = "volume_numbers.toml";
+
+// The chapters that went into each volume last time, so that a volume keeps both its number and
+//  its chapters after new ones come out
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct VolumeRecords {
+    // Keyed by the URL of each novel
+    novels: BTreeMap<String, Vec<VolumeRange>>,
+}
+#[derive(Debug, Deserialize, Serialize)]
+struct VolumeRange {
+    first_chapter: u32,
+    last_chapter: u32,
+}
+impl VolumeRecords {
+    pub fn load(save_dir: &Path) -> VolumeRecords {
+        let volume_path = save_dir.join(VOLUME_FILE_NAME);
+        let volume_text = match fs::read_to_string(&volume_path) {
+            Ok(volume_text) => volume_text,
+            // Nothing has been split up yet
+            Err(_) => return VolumeRecords::default(),
+        };
+        match toml::from_str(&volume_text) {
+            Ok(records) => records,
+            Err(e) => {
+                println!("Ignoring the unreadable {:?}: {:?}", &volume_path, e);
+                VolumeRecords::default()
+            },
+        }
+    }
+    pub fn save(&self, save_dir: &Path) -> NovelResult<()> {
+        fs::write(save_dir.join(VOLUME_FILE_NAME), toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    // Every volume but the last one stays exactly like it was. The last one could still be
+    //  filling up, so it gets grouped again along with all of the new sections
+    pub fn group_sections<'a>(&mut self, source_url: &str, volume_strategy: VolumeStrategy,
+    sections: &'a [Section]) -> Vec< Vec<(usize, &'a Section)> > {
+        let mut volumes = Vec::new();
+        let mut next_section = 0;
+        if let Some(ranges) = self.novels.get(source_url) {
+            for range in ranges.iter().take(ranges.len().saturating_sub(1)) {
+                match find_volume_end(sections, next_section, range) {
+                    Some(volume_end) => {
+                        volumes.push((next_section..volume_end)
+                            .map(|i| (i, &sections[i]))
+                            .collect());
+                        next_section = volume_end;
+                    },
+                    None => {
+                        println!("Volume {} of {} doesn't line up with its chapters anymore. \
+                            Numbering again from there", volumes.len() + 1, source_url);
+                        break;
+                    },
+                }
+            }
+        }
+        volumes.extend(volume_strategy.group_sections(&sections[next_section..]).into_iter()
+            .map(|volume| volume.into_iter()
+                .map(|(i, section)| (i + next_section, section))
+                .collect()));
+
+        let ranges = volumes.iter()
+            .filter_map(|volume: &Vec<(usize, &Section)>| {
+                let mut chapters = volume.iter().flat_map(|(_, section)| section.chapters.iter());
+                let first_chapter = chapters.next()?.order_num;
+                let last_chapter = chapters.last().map_or(first_chapter, |last| last.order_num);
+                Some(VolumeRange { first_chapter, last_chapter })
+            })
+            .collect();
+        self.novels.insert(source_url.to_string(), ranges);
+        volumes
+    }
+}
+
+// The index just after the volume's last section, as long as the sections still line up with it
+//  Empty sections go along with the volume that they are in
+fn find_volume_end(sections: &[Section], start: usize, range: &VolumeRange) -> Option<usize> {
+    let mut found_start = false;
+    for (i, section) in sections.iter().enumerate().skip(start) {
+        let (first, last) = match (section.chapters.first(), section.chapters.last()) {
+            (Some(first), Some(last)) => (first.order_num, last.order_num),
+            _ => continue,
+        };
+        if !found_start && first != range.first_chapter {
+            return None;
+        }
+        found_start = true;
+        if last == range.last_chapter {
+            return Some(i + 1);
+        } else if last > range.last_chapter {
+            return None;
+        }
+    }
+    None
+}
@@ -0,0 +1,28 @@
+use isahc::http::{Uri};
+
+use crate::{NovelResult};
+
+// Enough to tell if it's the right thing without filling the whole screen
+const SHOWN_MATCHES: usize = 5;
+const MAX_SHOWN_CHARS: usize = 80;
+
+// For finding out which selectors still work after a site changes its layout
+pub fn probe_selector(url: &str, selector: &str) -> NovelResult<()> {
+    let uri: Uri = url.parse()?;
+    let page_node = crate::fetch_page(&uri)?;
+    let matches: Vec<_> = match page_node.select(selector) {
+        Ok(matches) => matches.collect(),
+        Err(()) => {
+            println!("{} isn't a selector that can be used", selector);
+            return Ok(());
+        },
+    };
+    println!("{} matches {} nodes on {}", selector, matches.len(), &uri);
+    for (i, element) in matches.iter().take(SHOWN_MATCHES).enumerate() {
+        let text = element.text_contents().split_whitespace().collect::<Vec<_>>().join(" ");
+        let shown_text: String = text.chars().take(MAX_SHOWN_CHARS).collect();
+        let ellipsis = if text.chars().count() > MAX_SHOWN_CHARS { "…" } else { "" };
+        println!("    {}: {}{}", i + 1, shown_text, ellipsis);
+    }
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use std::{
+    time::{Duration},
+};
+
+use crate::{
+    NovelError,
+    novel::{Novel},
+};
+
+// Gets told about everything that happens while the novels are being made
+//  Something like a GUI can use its own instead of the log, to show how far along it is
+//  Chapters get fetched on many threads at once, so it has to be shareable between them
+pub trait ProgressObserver: Send + Sync {
+    fn on_novel_started(&self, short_name: &str);
+    fn on_chapter_fetched(&self, order_num: u32, chapter_name: &str);
+    fn on_novel_finished(&self, short_name: &str, novel: &Novel, elapsed: Duration);
+    fn on_error(&self, short_name: &str, error: &NovelError);
+}
+
+// What the command line uses. Every chapter would be too much for the log
+pub struct LoggingObserver;
+impl ProgressObserver for LoggingObserver {
+    fn on_novel_started(&self, short_name: &str) {
+        println!("Starting {}", short_name);
+    }
+    fn on_chapter_fetched(&self, _order_num: u32, _chapter_name: &str) {}
+    fn on_novel_finished(&self, short_name: &str, novel: &Novel, elapsed: Duration) {
+        println!("Finished {} ({}) with {} chapters in {} sections in {:?}",
+            novel.print_name(), short_name, novel.chapter_count(), novel.section_count(),
+            elapsed);
+    }
+    fn on_error(&self, short_name: &str, error: &NovelError) {
+        println!("Failed {}: {:?}", short_name, error);
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::{
+        HashMap,
+        hash_map::{RandomState},
+    },
+    hash::{BuildHasher, Hash, Hasher},
+    sync::{Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use serde::{Deserialize};
+
+// Spreads requests out evenly so that no more than the limit go out in any minute
+//  This is shared by every thread, no matter which novel or site they are working on
+pub struct RequestLimiter {
+    interval: Duration,
+    jitter_percent: u32,
+    next_slot: Mutex<Instant>,
+}
+impl RequestLimiter {
+    pub fn new(requests_per_minute: u32, jitter_percent: u32) -> RequestLimiter {
+        let requests_per_minute = requests_per_minute.max(1);
+        RequestLimiter {
+            interval: Duration::from_secs(60) / requests_per_minute,
+            jitter_percent,
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    // Blocks until it's this thread's turn to make a request
+    pub fn wait_for_turn(&self) {
+        let my_slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let my_slot = (*next_slot).max(Instant::now());
+            *next_slot = my_slot + jittered(self.interval, self.jitter_percent);
+            my_slot
+        };
+        let now = Instant::now();
+        if my_slot > now {
+            thread::sleep(my_slot - now);
+        }
+    }
+}
+
+// Where the delay for a host starts once it gets throttled, before it gets doubled
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct BackoffOptions {
+    // Failures in a row (to the same host) before the delay goes up
+    #[serde(default = "default_failures_to_slow_down")]
+    failures_to_slow_down: u32,
+    // Successes in a row before the delay comes back down
+    #[serde(default = "default_successes_to_speed_up")]
+    successes_to_speed_up: u32,
+    max_delay_secs: u64,
+}
+fn default_failures_to_slow_down() -> u32 { 3 }
+fn default_successes_to_speed_up() -> u32 { 20 }
+
+// Slows down every request to a host that keeps failing, then speeds back up once it recovers
+//  Each host is kept separate, so one slow site doesn't hold back the rest
+pub struct HostBackoff {
+    options: BackoffOptions,
+    jitter_percent: u32,
+    hosts: Mutex< HashMap<String, HostState> >,
+}
+#[derive(Debug, Default)]
+struct HostState {
+    delay: Duration,
+    failures_in_a_row: u32,
+    successes_in_a_row: u32,
+}
+impl HostBackoff {
+    pub fn new(options: BackoffOptions, jitter_percent: u32) -> HostBackoff {
+        HostBackoff {
+            options,
+            jitter_percent,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn wait_for_host(&self, host: &str) {
+        let delay = self.hosts.lock().unwrap().get(host)
+            .map_or(Duration::from_secs(0), |state| state.delay);
+        if delay > Duration::from_secs(0) {
+            thread::sleep(jittered(delay, self.jitter_percent));
+        }
+    }
+
+    pub fn record_success(&self, host: &str) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host.to_string()).or_default();
+        state.failures_in_a_row = 0;
+        state.successes_in_a_row += 1;
+        if state.successes_in_a_row >= self.options.successes_to_speed_up
+        && state.delay > Duration::from_secs(0) {
+            state.successes_in_a_row = 0;
+            state.delay = if state.delay > FIRST_BACKOFF {
+                state.delay / 2
+            } else {
+                Duration::from_secs(0)
+            };
+            println!("Speeding {} back up to {:?} between requests", host, state.delay);
+        }
+    }
+
+    pub fn record_failure(&self, host: &str) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host.to_string()).or_default();
+        state.successes_in_a_row = 0;
+        state.failures_in_a_row += 1;
+        if state.failures_in_a_row >= self.options.failures_to_slow_down {
+            state.failures_in_a_row = 0;
+            let max_delay = Duration::from_secs(self.options.max_delay_secs);
+            state.delay = if state.delay == Duration::from_secs(0) {
+                FIRST_BACKOFF
+            } else {
+                state.delay * 2
+            }.min(max_delay);
+            println!("Slowing {} down to {:?} between requests", host, state.delay);
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct OutageOptions {
+    // Server errors in a row (from the same host) before it's taken as down
+    #[serde(default = "default_errors_to_pause")]
+    errors_to_pause: u32,
+    cooldown_mins: u64,
+    // Once these are used up without the host coming back, it gets skipped for the rest of the run
+    #[serde(default = "default_max_cooldowns")]
+    max_cooldowns: u32,
+}
+fn default_errors_to_pause() -> u32 { 5 }
+fn default_max_cooldowns() -> u32 { 1 }
+
+// Stops every request to a host that keeps sending back server errors, like during maintenance
+//  It gets a few long breaks to come back, and then every novel from it fails right away
+pub struct HostOutages {
+    options: OutageOptions,
+    hosts: Mutex< HashMap<String, OutageState> >,
+}
+#[derive(Debug, Default)]
+struct OutageState {
+    errors_in_a_row: u32,
+    cooldowns_used: u32,
+    paused_until: Option<Instant>,
+    is_down: bool,
+}
+impl HostOutages {
+    pub fn new(options: OutageOptions) -> HostOutages {
+        HostOutages {
+            options,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Gives back false when the host is down for good, so nothing should be sent to it
+    pub fn wait_for_host(&self, host: &str) -> bool {
+        let paused_until = match self.hosts.lock().unwrap().get(host) {
+            Some(state) if state.is_down => return false,
+            Some(state) => state.paused_until,
+            None => None,
+        };
+        if let Some(paused_until) = paused_until {
+            let now = Instant::now();
+            if paused_until > now {
+                thread::sleep(paused_until - now);
+            }
+        }
+        true
+    }
+
+    pub fn record_success(&self, host: &str) {
+        if let Some(state) = self.hosts.lock().unwrap().get_mut(host) {
+            *state = OutageState::default();
+        }
+    }
+
+    // Gives back true when the host is taking a break, which means the request can be tried
+    //  again afterwards without counting it as a retry
+    pub fn record_server_error(&self, host: &str) -> bool {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host.to_string()).or_default();
+        // Every thread that was already waiting on the host will fail as well
+        let now = Instant::now();
+        if state.is_down {
+            return false;
+        } else if state.paused_until.map_or(false, |paused_until| paused_until > now) {
+            return true;
+        }
+        state.errors_in_a_row += 1;
+        if state.errors_in_a_row < self.options.errors_to_pause {
+            return false;
+        }
+        state.errors_in_a_row = 0;
+        if state.cooldowns_used < self.options.max_cooldowns {
+            state.cooldowns_used += 1;
+            state.paused_until = Some(now + Duration::from_secs(self.options.cooldown_mins * 60));
+            println!("{} appears to be down. Waiting {} minutes before trying it again", host,
+                self.options.cooldown_mins);
+            true
+        } else {
+            state.is_down = true;
+            println!("{} appears to be down. Skipping everything else from it", host);
+            false
+        }
+    }
+}
+
+// Moves the delay up or down by a random amount (up to the percent), so that the requests
+//  don't go out like clockwork. Anything over 100% is the same as 100%
+pub fn jittered(delay: Duration, jitter_percent: u32) -> Duration {
+    let jitter_percent = jitter_percent.min(100) as u64;
+    if jitter_percent == 0 {
+        return delay;
+    }
+    // std only seeds the hash keys randomly once per thread, then counts them up by one for each
+    //  new RandomState. SipHash still scatters those keys (along with the time) well enough
+    //  for a delay, without pulling in a whole random number library
+    let mut hasher = RandomState::new().build_hasher();
+    SystemTime::now().hash(&mut hasher);
+    let random = hasher.finish();
+    // Somewhere from -jitter_percent to +jitter_percent, in tenths of a percent
+    let jitter_range = jitter_percent * 10;
+    let jitter_tenths = (random % (jitter_range * 2 + 1)) as i64 - jitter_range as i64;
+    let delay_millis = delay.as_millis() as i64;
+    let jittered_millis = delay_millis + delay_millis * jitter_tenths / 1000;
+    Duration::from_millis(jittered_millis.max(0) as u64)
+}
@@ -0,0 +1,80 @@
+use std::{
+    fs,
+    path::{Path},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    NovelError, NovelInfo, NovelResult, RunInfo,
+};
+
+// Lives in the save directory next to the books, like the statuses
+const RUN_REPORT_FILE_NAME: &'static str = "run_report.json";
+
+// What happened to every novel in the last run, so that only the failed ones need to be run again
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RunReport {
+    finished: Vec<ReportedNovel>,
+    // Along with the ones that got skipped when we ran out of time
+    failed: Vec<ReportedNovel>,
+    // Only by their titles, since they don't have a URL of their own
+    failed_omnibuses: Vec<String>,
+}
+// Both are kept so that a novel from a collection (which isn't in the config) can be made again
+#[derive(Debug, Deserialize, Serialize)]
+struct ReportedNovel {
+    short_name: String,
+    // Empty for local novels
+    url: String,
+    // Only for the failed ones
+    #[serde(default)]
+    error: Option<String>,
+}
+impl RunReport {
+    // There's nothing to retry without the last report, so it has to be there
+    pub fn load(save_dir: &Path) -> NovelResult<RunReport> {
+        let report_text = fs::read_to_string(save_dir.join(RUN_REPORT_FILE_NAME))?;
+        Ok(serde_json::from_str(&report_text)?)
+    }
+    pub fn save(&self, save_dir: &Path) -> NovelResult<()> {
+        fs::write(save_dir.join(RUN_REPORT_FILE_NAME), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn record_finished(&mut self, novel_info: &NovelInfo) {
+        self.finished.push(ReportedNovel {
+            short_name: novel_info.short_name.clone(),
+            url: novel_info.url.clone(),
+            error: None,
+        });
+    }
+    pub fn record_failed(&mut self, novel_info: &NovelInfo, error: &NovelError) {
+        self.failed.push(ReportedNovel {
+            short_name: novel_info.short_name.clone(),
+            url: novel_info.url.clone(),
+            error: Some(format!("{:?}", error)),
+        });
+    }
+    pub fn record_failed_omnibus(&mut self, title: &str) {
+        self.failed_omnibuses.push(title.to_string());
+    }
+
+    pub fn has_failed(&self, novel_info: &NovelInfo) -> bool {
+        self.failed.iter().any(|failed| {
+            failed.short_name == novel_info.short_name && failed.url == novel_info.url
+        })
+    }
+    pub fn has_failed_omnibus(&self, title: &str) -> bool {
+        self.failed_omnibuses.iter().any(|failed_title| failed_title == title)
+    }
+    // The failed novels that came from a collection, so that the collection doesn't have to be
+    //  fetched again just to find them
+    pub fn failed_unlisted_novels(&self, run_info: &RunInfo) -> Vec<NovelInfo> {
+        self.failed.iter()
+            .filter(|failed| !failed.url.is_empty())
+            .filter(|failed| !run_info.novels.iter().any(|novel_info| novel_info.url == failed.url))
+            .map(|failed| NovelInfo::from_url(failed.url.clone()))
+            .collect()
+    }
+    pub fn failed_count(&self) -> usize { self.failed.len() + self.failed_omnibuses.len() }
+}
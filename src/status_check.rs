@@ -0,0 +1,136 @@
+use std::{
+    collections::{BTreeMap},
+    fs,
+    path::{Path},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    NovelResult, RunInfo,
+    novel::{NovelStatus},
+};
+
+// Lives in the save directory next to the books so it follows them around
+const STATUS_FILE_NAME: &'static str = "novel_status.toml";
+// Only a build writes this one, so checking the statuses can't make a novel look like it was made
+const BUILT_FILE_NAME: &'static str = "novel_built.toml";
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct StatusRecords {
+    // Keyed by the short name of each novel
+    novels: BTreeMap<String, StatusRecord>,
+}
+impl StatusRecords {
+    pub fn load(save_dir: &Path) -> StatusRecords {
+        Self::load_file(&save_dir.join(STATUS_FILE_NAME))
+    }
+    // What each novel looked like the last time it was made
+    pub fn load_built(save_dir: &Path) -> StatusRecords {
+        Self::load_file(&save_dir.join(BUILT_FILE_NAME))
+    }
+    fn load_file(status_path: &Path) -> StatusRecords {
+        let status_text = match fs::read_to_string(&status_path) {
+            Ok(status_text) => status_text,
+            // We just haven't checked anything yet
+            Err(_) => return StatusRecords::default(),
+        };
+        match toml::from_str(&status_text) {
+            Ok(records) => records,
+            Err(e) => {
+                println!("Ignoring the unreadable {:?}: {:?}", status_path, e);
+                StatusRecords::default()
+            },
+        }
+    }
+    pub fn save(&self, save_dir: &Path) -> NovelResult<()> {
+        self.save_file(&save_dir.join(STATUS_FILE_NAME))
+    }
+    pub fn save_built(&self, save_dir: &Path) -> NovelResult<()> {
+        self.save_file(&save_dir.join(BUILT_FILE_NAME))
+    }
+    fn save_file(&self, status_path: &Path) -> NovelResult<()> {
+        fs::write(status_path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    // True when the last run already saw the novel exactly like this
+    pub fn is_unchanged(&self, short_name: &str, status: NovelStatus, chapter_count: u32) -> bool {
+        self.novels.get(short_name).map_or(false, |record| {
+            record.status == status && record.chapter_count == chapter_count
+        })
+    }
+
+    // Gives back whatever was recorded before
+    pub fn record(&mut self, short_name: &str, status: NovelStatus, chapter_count: u32)
+    -> Option<StatusRecord> {
+        self.novels.insert(short_name.to_string(), StatusRecord {
+            status,
+            chapter_count,
+        })
+    }
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StatusRecord {
+    status: NovelStatus,
+    chapter_count: u32,
+}
+
+// Only looks at the main pages of each novel so nothing gets downloaded or rebuilt
+pub fn check_statuses(run_info: &RunInfo) {
+    let mut records = StatusRecords::load(&run_info.save_dir);
+    for novel_info in run_info.novel_batch() {
+        let overview = match crate::fetch_novel_overview(novel_info) {
+            Ok(overview) => overview,
+            Err(e) => {
+                println!("Failed to check {}: {:?}", &novel_info.short_name, e);
+                continue;
+            },
+        };
+        let previous = records.record(&novel_info.short_name, overview.status,
+            overview.chapter_count);
+        let print_name = format!("{} ({})", overview.print_name(), &novel_info.short_name);
+        match previous {
+            Some(previous) => {
+                if previous.status != overview.status {
+                    println!("{}: {} -> {}", print_name,
+                        previous.status.status_text(), overview.status.status_text());
+                }
+                if previous.chapter_count != overview.chapter_count {
+                    println!("{}: {} -> {} chapters", print_name,
+                        previous.chapter_count, overview.chapter_count);
+                }
+            },
+            None => println!("{}: {} with {} chapters", print_name,
+                overview.status.status_text(), overview.chapter_count),
+        }
+    }
+    if let Err(e) = records.save(&run_info.save_dir) {
+        println!("Failed to save the statuses: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checking_the_statuses_leaves_the_built_records_alone() {
+        let save_dir = std::env::temp_dir().join(format!("novel_status_test_{}",
+            std::process::id()));
+        fs::create_dir_all(&save_dir).unwrap();
+
+        // Like running status after the novel finished, but before it was made again
+        let mut status_records = StatusRecords::default();
+        status_records.record("novel", NovelStatus::Finished, 100);
+        status_records.save(&save_dir).unwrap();
+        let mut built_records = StatusRecords::default();
+        built_records.record("novel", NovelStatus::Running, 99);
+        built_records.save_built(&save_dir).unwrap();
+
+        assert!(StatusRecords::load(&save_dir).is_unchanged("novel", NovelStatus::Finished, 100));
+        let built_records = StatusRecords::load_built(&save_dir);
+        assert!(!built_records.is_unchanged("novel", NovelStatus::Finished, 100));
+        assert!(built_records.is_unchanged("novel", NovelStatus::Running, 99));
+        fs::remove_dir_all(&save_dir).unwrap();
+    }
+}